@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use geo::{Centroid, Coord, LineString, Point, Polygon};
 use osm_reader::{NodeID, OsmID, RelationID, WayID};
@@ -11,6 +11,9 @@ pub struct Areas {
 
     pub other_polygons: RTree<Polygon>,
     pub other_centroids: RTree<Point>,
+
+    /// Nodes tagged `highway=mini_roundabout`
+    pub mini_roundabouts: HashSet<NodeID>,
 }
 
 // TODO Ignores holes
@@ -19,6 +22,7 @@ pub struct ReadOsmAreas {
     // TODO Maybe OsmID to capture relations fully
     polygons: Vec<(WayID, AreaKind, Polygon)>,
     possible_area_parts: HashMap<WayID, Polygon>,
+    mini_roundabouts: HashSet<NodeID>,
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -43,7 +47,11 @@ impl AreaKind {
 }
 
 impl OsmReader for ReadOsmAreas {
-    fn node(&mut self, _: NodeID, _: Coord, _: Tags) {}
+    fn node(&mut self, id: NodeID, _: Coord, tags: Tags) {
+        if tags.is("highway", "mini_roundabout") {
+            self.mini_roundabouts.insert(id);
+        }
+    }
 
     fn way(
         &mut self,
@@ -56,7 +64,7 @@ impl OsmReader for ReadOsmAreas {
             self.possible_area_parts.insert(
                 id,
                 Polygon::new(
-                    LineString::new(node_ids.into_iter().map(|n| node_mapping[n]).collect()),
+                    LineString::new(node_ids.iter().map(|n| node_mapping[n]).collect()),
                     Vec::new(),
                 ),
             );
@@ -64,7 +72,7 @@ impl OsmReader for ReadOsmAreas {
         }
 
         if let Some(kind) = AreaKind::from_tags(tags) {
-            let exterior = LineString::new(node_ids.into_iter().map(|n| node_mapping[n]).collect());
+            let exterior = LineString::new(node_ids.iter().map(|n| node_mapping[n]).collect());
             self.polygons
                 .push((id, kind, Polygon::new(exterior, Vec::new())));
         }
@@ -80,7 +88,7 @@ impl OsmReader for ReadOsmAreas {
                 continue;
             }
             if let OsmID::Way(way) = id {
-                if let Some(polygon) = self.possible_area_parts.remove(&way) {
+                if let Some(polygon) = self.possible_area_parts.remove(way) {
                     self.polygons.push((*way, kind, polygon));
                 }
             }
@@ -114,6 +122,7 @@ impl ReadOsmAreas {
             building_centroids,
             other_polygons,
             other_centroids,
+            mini_roundabouts: self.mini_roundabouts,
         }
     }
 }
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use backend::{Recipe, RoadBundler};
+use clap::Parser;
+use geojson::GeoJson;
+
+/// Apply a sequence of simplifications to an osm.xml or osm.pbf file, writing the simplified
+/// network as GeoJSON
+#[derive(Parser)]
+struct Args {
+    /// Path to an osm.xml or osm.pbf file
+    #[arg(long)]
+    input: PathBuf,
+
+    /// Path to write a GeoJSON file with the simplified edges and intersections. Every edge
+    /// describes the OSM ways bundled into it.
+    #[arg(long)]
+    output: PathBuf,
+
+    /// Path to a JSON recipe file, listing the operations to run and their parameters
+    #[arg(long)]
+    recipe: Option<PathBuf>,
+
+    /// Path to write a JSON report explaining why faces left over after the recipe weren't
+    /// handled as dual carriageways, roundabouts, or sidepaths
+    #[arg(long)]
+    diagnostics: Option<PathBuf>,
+
+    /// Path to write a JOSM .osm file adding `is_sidepath`, `is_sidepath:of`, and
+    /// `is_sidepath:of:name` to the footways and cycleways matched as sidepaths, for review. The
+    /// input must record element versions.
+    #[arg(long)]
+    josm_osm: Option<PathBuf>,
+
+    /// Operations to run after the recipe, in order, using default parameters. These're named
+    /// the same as the methods in the web app, like `fixAllDualCarriageways`.
+    operations: Vec<String>,
+}
+
+fn main() -> Result<()> {
+    simple_logger::init_with_level(log::Level::Info)?;
+    let args = Args::parse();
+
+    let mut recipe = match args.recipe {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => Recipe::default(),
+    };
+    for operation in args.operations {
+        recipe.steps.push(serde_json::from_value(
+            serde_json::json!({ "operation": operation }),
+        )?);
+    }
+    if recipe.steps.is_empty() {
+        bail!("Specify a --recipe or some operations to run");
+    }
+
+    let input_bytes = std::fs::read(&args.input)?;
+    let mut bundler = RoadBundler::from_osm(&input_bytes)?;
+    bundler.apply_recipe(&recipe)?;
+
+    let mut features = bundler.bundled_edges_to_gj()?;
+    features.extend(bundler.intersections_to_gj()?);
+    std::fs::write(
+        &args.output,
+        serde_json::to_string(&GeoJson::from(features))?,
+    )?;
+
+    if let Some(path) = args.diagnostics {
+        std::fs::write(path, serde_json::to_string(&bundler.diagnostics())?)?;
+    }
+    if let Some(path) = args.josm_osm {
+        std::fs::write(path, bundler.sidepath_tags_to_josm_osm(&input_bytes)?)?;
+    }
+    Ok(())
+}
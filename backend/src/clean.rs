@@ -1,18 +1,26 @@
+use anyhow::Result;
 use geo::LineString;
 
 use crate::{EdgeID, IntersectionID, RoadBundler};
 
 impl RoadBundler {
-    pub fn remove_edge(&mut self, id: EdgeID) {
-        let edge = self.graph.remove_edge(id);
+    pub fn remove_edge(&mut self, id: EdgeID) -> Result<()> {
+        let edge = self.graph.remove_edge(id)?;
         for i in [edge.src, edge.dst] {
-            if self.graph.intersections[&i].edges.is_empty() {
-                self.graph.remove_empty_intersection(i);
+            // If edge.src == edge.dst, the first iteration removes it
+            if self
+                .graph
+                .intersections
+                .get(&i)
+                .is_some_and(|i| i.edges.is_empty())
+            {
+                self.graph.remove_empty_intersection(i)?;
             }
         }
+        Ok(())
     }
 
-    pub fn remove_all_service_roads(&mut self) {
+    pub fn remove_all_service_roads(&mut self) -> Result<()> {
         let remove_edges: Vec<_> = self
             .graph
             .edges
@@ -21,18 +29,22 @@ impl RoadBundler {
             .map(|(id, _)| *id)
             .collect();
         for e in remove_edges {
-            self.graph.remove_edge(e);
+            self.graph.remove_edge(e)?;
         }
 
         self.graph.remove_all_empty_intersections();
+        Ok(())
     }
 
-    pub fn collapse_degenerate_intersection(&mut self, id: IntersectionID) {
-        let edges = self.graph.intersections[&id].edges.clone();
+    pub fn collapse_degenerate_intersection(&mut self, id: IntersectionID) -> Result<()> {
+        let Some(intersection) = self.graph.intersections.get(&id) else {
+            bail!("{id:?} doesn't exist");
+        };
+        let edges = intersection.edges.clone();
         // Silently do nothing?
         // TODO in city of london, collapsing all creates a new case of a self-loop somewhere
         if edges.len() != 2 || edges[0] == edges[1] {
-            return;
+            return Ok(());
         }
 
         // Can't combine a motorized and nonmotorized edge. Again, maybe weird to silently do
@@ -41,12 +53,12 @@ impl RoadBundler {
             .kind
             .merge(&self.graph.edges[&edges[1]].kind)
         else {
-            return;
+            return Ok(());
         };
 
-        let mut edge1 = self.graph.remove_edge(edges[0]);
-        let mut edge2 = self.graph.remove_edge(edges[1]);
-        self.graph.remove_empty_intersection(id);
+        let mut edge1 = self.graph.remove_edge(edges[0])?;
+        let mut edge2 = self.graph.remove_edge(edges[1])?;
+        self.graph.remove_empty_intersection(id)?;
 
         // Make edge1 point to id
         let mut pts = Vec::new();
@@ -68,6 +80,7 @@ impl RoadBundler {
         pts.extend(edge2.linestring.0);
 
         self.graph
-            .create_new_edge(LineString::new(pts), i1, i2, kind);
+            .create_new_edge(LineString::new(pts), i1, i2, kind)?;
+        Ok(())
     }
 }
@@ -0,0 +1,496 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem::discriminant;
+
+use anyhow::Result;
+use geo::{Euclidean, Length, LineLocatePoint, Point};
+use osm_reader::WayID;
+use serde::Serialize;
+use utils::Tags;
+
+use crate::dual_carriageway::{main_road_name, original_carriageway_role, CarriagewayRole};
+use crate::export::Direction;
+use crate::geo_helpers::{runs_along, signed_offset};
+use crate::graph::{Edge, OriginalEdgeID};
+use crate::{EdgeID, EdgeKind, RoadBundler};
+
+/// Ways bundled into an edge closer together than this are one after the other along the edge,
+/// not side by side
+const SAME_OFFSET_METERS: f64 = 2.0;
+/// Gaps between carriageways narrower than this aren't worth calling a median
+const MIN_MEDIAN_WIDTH: f64 = 0.5;
+
+/// Everything bundled into one edge, ordered left to right when looking from `src` to `dst`
+#[derive(Serialize)]
+pub struct CrossSection {
+    pub components: Vec<Component>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Component {
+    #[serde(flatten)]
+    pub kind: ComponentKind,
+    /// Estimated from tags, in meters
+    pub width: f64,
+    /// Where this component comes from. Medians don't come from any way.
+    pub ways: Vec<WayID>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum ComponentKind {
+    /// A separately mapped footway
+    Sidewalk,
+    /// A separately mapped cycleway
+    Cycleway,
+    /// A separately mapped path for walking and cycling
+    SharedPath,
+    /// A cycle lane or track tagged on the road
+    CycleLane,
+    /// Parking tagged on the road
+    Parking,
+    Lane {
+        role: CarriagewayRole,
+        /// Relative to the edge
+        direction: Direction,
+        /// From `turn:lanes`, like `left|through`
+        turn: Option<String>,
+    },
+    /// The space between two carriageways
+    Median,
+}
+
+/// Components from one OSM way, or several one after the other, placed across the edge
+struct Block {
+    /// Meters from the edge, positive to the right
+    offset: f64,
+    /// Left to right
+    components: Vec<Component>,
+    is_road: bool,
+    length: f64,
+}
+
+impl Block {
+    fn width(&self) -> f64 {
+        self.components.iter().map(|c| c.width).sum()
+    }
+
+    /// Can the other block be merged into this one?
+    fn continues(&self, other: &Block) -> bool {
+        self.is_road == other.is_road
+            && (self.is_road
+                || discriminant(&self.components[0].kind)
+                    == discriminant(&other.components[0].kind))
+            && (self.offset - other.offset).abs() < SAME_OFFSET_METERS
+    }
+
+    /// Keeps the components of the longer block, and all of the ways
+    fn merge(self, other: Block) -> Block {
+        let (mut keep, other) = if self.length >= other.length {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let other_ways: BTreeSet<WayID> =
+            other.components.into_iter().flat_map(|c| c.ways).collect();
+        for component in &mut keep.components {
+            for way in &other_ways {
+                if !component.ways.contains(way) {
+                    component.ways.push(*way);
+                }
+            }
+        }
+        keep.length += other.length;
+        keep
+    }
+}
+
+impl RoadBundler {
+    /// Describes everything bundled into an edge from left to right. Which side separately mapped
+    /// ways are on comes from their original geometry, and the lanes of each road come from tags.
+    pub fn get_cross_section(&self, e: EdgeID) -> Result<CrossSection> {
+        let Some(edge) = self.graph.edges.get(&e) else {
+            bail!("{e:?} doesn't exist");
+        };
+        let empty = BTreeSet::new();
+        let (roads, service_roads, paths) = match &edge.kind {
+            EdgeKind::Motorized {
+                roads,
+                service_roads,
+                sidepaths,
+                ..
+            } => (roads, service_roads, sidepaths),
+            EdgeKind::Nonmotorized(edges) => (&empty, &empty, edges),
+        };
+        let name = main_road_name(&self.graph, &edge.kind);
+
+        let mut blocks = Vec::new();
+        for (original_edges, service_road) in [(roads, false), (service_roads, true)] {
+            for (way, pieces) in self.group_pieces(original_edges) {
+                // Like pieces of side roads crossing between collapsed carriageways
+                if !self.is_parallel(edge, &pieces) {
+                    continue;
+                }
+                let tags = &self.graph.original_edges[&pieces[0]].tags;
+                let role = original_carriageway_role(tags, service_road, name);
+                let along = runs_along(
+                    &edge.linestring,
+                    &self.original_graph.edges[&EdgeID(pieces[0].0)].linestring,
+                );
+                blocks.push(Block {
+                    offset: self.offset(edge, &pieces),
+                    components: road_components(tags, way, role, along),
+                    is_road: true,
+                    length: self.length(&pieces),
+                });
+            }
+        }
+        for (way, pieces) in self.group_pieces(paths) {
+            if !self.is_parallel(edge, &pieces) {
+                continue;
+            }
+            let tags = &self.graph.original_edges[&pieces[0]].tags;
+            let kind = path_kind(tags);
+            let width = width(tags).unwrap_or(match kind {
+                ComponentKind::SharedPath => 3.0,
+                _ => 2.0,
+            });
+            // Prefer where the sidepath was when it was bundled, relative to the road
+            let known: Vec<f64> = pieces
+                .iter()
+                .filter_map(|piece| self.sidepath_offset(e, *piece))
+                .collect();
+            let offset = if known.is_empty() {
+                self.offset(edge, &pieces)
+            } else {
+                known.iter().sum::<f64>() / known.len() as f64
+            };
+            blocks.push(Block {
+                offset,
+                components: vec![Component {
+                    kind,
+                    width,
+                    ways: vec![way],
+                }],
+                is_road: false,
+                length: self.length(&pieces),
+            });
+        }
+
+        blocks.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+        let mut merged: Vec<Block> = Vec::new();
+        for block in blocks {
+            match merged.pop() {
+                Some(last) if last.continues(&block) => merged.push(last.merge(block)),
+                Some(last) => {
+                    merged.push(last);
+                    merged.push(block);
+                }
+                None => merged.push(block),
+            }
+        }
+
+        let mut components = Vec::new();
+        for (idx, block) in merged.iter().enumerate() {
+            if idx > 0 && block.is_road && merged[idx - 1].is_road {
+                let prev = &merged[idx - 1];
+                let gap = block.offset - prev.offset - (prev.width() + block.width()) / 2.0;
+                if gap > MIN_MEDIAN_WIDTH {
+                    components.push(Component {
+                        kind: ComponentKind::Median,
+                        width: gap,
+                        ways: Vec::new(),
+                    });
+                }
+            }
+            components.extend(block.components.iter().cloned());
+        }
+        Ok(CrossSection { components })
+    }
+
+    /// Groups original edges by their OSM way
+    fn group_pieces(
+        &self,
+        original_edges: &BTreeSet<OriginalEdgeID>,
+    ) -> BTreeMap<WayID, Vec<OriginalEdgeID>> {
+        let mut by_way: BTreeMap<WayID, Vec<OriginalEdgeID>> = BTreeMap::new();
+        for e in original_edges {
+            by_way
+                .entry(self.graph.original_edges[e].way)
+                .or_default()
+                .push(*e);
+        }
+        by_way
+    }
+
+    /// How far the original geometry is from the edge on average, positive to the right
+    fn offset(&self, edge: &Edge, pieces: &[OriginalEdgeID]) -> f64 {
+        let offsets: Vec<f64> = pieces
+            .iter()
+            .map(|e| {
+                signed_offset(
+                    &edge.linestring,
+                    &self.original_graph.edges[&EdgeID(e.0)].linestring,
+                )
+            })
+            .collect();
+        offsets.iter().sum::<f64>() / offsets.len() as f64
+    }
+
+    /// Is most of the original geometry alongside the edge, not crossing it?
+    fn is_parallel(&self, edge: &Edge, pieces: &[OriginalEdgeID]) -> bool {
+        let edge_length = Euclidean.length(&edge.linestring);
+        let beside: f64 = pieces
+            .iter()
+            .map(|e| {
+                let ls = &self.original_graph.edges[&EdgeID(e.0)].linestring;
+                let fractions: Vec<f64> = [ls.0.first(), ls.0.last()]
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|pt| edge.linestring.line_locate_point(&Point::from(*pt)))
+                    .collect();
+                match fractions.as_slice() {
+                    [start, end] => (end - start).abs() * edge_length,
+                    _ => 0.0,
+                }
+            })
+            .sum();
+        // Sidepaths can be bundled into many edges, so they might be much longer than this one
+        beside >= 0.5 * self.length(pieces).min(edge_length)
+    }
+
+    fn length(&self, pieces: &[OriginalEdgeID]) -> f64 {
+        pieces
+            .iter()
+            .map(|e| Euclidean.length(&self.original_graph.edges[&EdgeID(e.0)].linestring))
+            .sum()
+    }
+}
+
+/// Lanes, cycle lanes, and parking tagged on one road, left to right looking along the edge.
+/// `along` is true if the way points the same way as the edge.
+fn road_components(tags: &Tags, way: WayID, role: CarriagewayRole, along: bool) -> Vec<Component> {
+    // Relative to the way. Each group of lanes is in order for traffic travelling that way.
+    let (forward_lanes, backward_lanes) = if tags.is("oneway", "yes") {
+        (lanes(tags, "lanes").unwrap_or(1), 0)
+    } else if tags.is("oneway", "-1") {
+        (0, lanes(tags, "lanes").unwrap_or(1))
+    } else {
+        match (
+            lanes(tags, "lanes:forward"),
+            lanes(tags, "lanes:backward"),
+            lanes(tags, "lanes"),
+        ) {
+            (Some(forward), Some(backward), _) => (forward, backward),
+            (Some(forward), None, Some(total)) => (forward, total.saturating_sub(forward)),
+            (None, Some(backward), Some(total)) => (total.saturating_sub(backward), backward),
+            (_, _, Some(total)) => (total - total / 2, total / 2),
+            _ => (1, 1),
+        }
+    };
+    let oneway = forward_lanes == 0 || backward_lanes == 0;
+    let turn_lanes = |direction: &str| {
+        let key = if oneway {
+            "turn:lanes".to_string()
+        } else {
+            format!("turn:lanes:{direction}")
+        };
+        tags.get(&key)
+            .map(|x| x.split('|').map(|x| x.to_string()).collect::<Vec<_>>())
+            .unwrap_or_default()
+    };
+    let total_lanes = forward_lanes + backward_lanes;
+    let lane_width = width(tags).map(|x| x / total_lanes as f64).unwrap_or(
+        if role == CarriagewayRole::Service {
+            2.5
+        } else {
+            3.0
+        },
+    );
+
+    let lane = |direction, turn: Option<&String>| Component {
+        kind: ComponentKind::Lane {
+            role,
+            direction,
+            turn: turn.cloned(),
+        },
+        width: lane_width,
+        ways: vec![way],
+    };
+    let side_component = |kind, width| Component {
+        kind,
+        width,
+        ways: vec![way],
+    };
+
+    // Left to right relative to the way. Assume traffic drives on the right, so backward lanes
+    // are on the left, listed in reverse.
+    let mut components = Vec::new();
+    if has_parking(tags, "left") {
+        components.push(side_component(ComponentKind::Parking, 2.0));
+    }
+    if has_cycle_lane(tags, "left") {
+        components.push(side_component(ComponentKind::CycleLane, 1.5));
+    }
+    let backward_turns = turn_lanes("backward");
+    for idx in (0..backward_lanes).rev() {
+        components.push(lane(Direction::Backward, backward_turns.get(idx)));
+    }
+    let forward_turns = turn_lanes("forward");
+    for idx in 0..forward_lanes {
+        components.push(lane(Direction::Forward, forward_turns.get(idx)));
+    }
+    if has_cycle_lane(tags, "right") {
+        components.push(side_component(ComponentKind::CycleLane, 1.5));
+    }
+    if has_parking(tags, "right") {
+        components.push(side_component(ComponentKind::Parking, 2.0));
+    }
+
+    if !along {
+        components.reverse();
+        for component in &mut components {
+            if let ComponentKind::Lane {
+                ref mut direction, ..
+            } = component.kind
+            {
+                *direction = match direction {
+                    Direction::Forward => Direction::Backward,
+                    Direction::Backward => Direction::Forward,
+                };
+            }
+        }
+    }
+    components
+}
+
+fn path_kind(tags: &Tags) -> ComponentKind {
+    let bicycle = tags.is_any("bicycle", vec!["yes", "designated"]);
+    let foot = tags.is_any("foot", vec!["yes", "designated"]);
+    if tags.is("highway", "cycleway") {
+        if foot && !tags.is("segregated", "yes") {
+            ComponentKind::SharedPath
+        } else {
+            ComponentKind::Cycleway
+        }
+    } else if bicycle && tags.is_any("highway", vec!["path", "footway"]) {
+        ComponentKind::SharedPath
+    } else {
+        ComponentKind::Sidewalk
+    }
+}
+
+fn lanes(tags: &Tags, key: &str) -> Option<usize> {
+    tags.get(key)?.parse().ok()
+}
+
+/// Parses `width`, ignoring units
+fn width(tags: &Tags) -> Option<f64> {
+    tags.get("width")?.trim_end_matches('m').trim().parse().ok()
+}
+
+fn has_parking(tags: &Tags, side: &str) -> bool {
+    for key in [format!("parking:{side}"), "parking:both".to_string()] {
+        if tags.is_any(
+            &key,
+            vec!["lane", "street_side", "on_kerb", "half_on_kerb", "shoulder"],
+        ) {
+            return true;
+        }
+    }
+    // The older scheme
+    for key in [
+        format!("parking:lane:{side}"),
+        "parking:lane:both".to_string(),
+    ] {
+        if tags.is_any(
+            &key,
+            vec!["parallel", "diagonal", "perpendicular", "marked"],
+        ) {
+            return true;
+        }
+    }
+    false
+}
+
+fn has_cycle_lane(tags: &Tags, side: &str) -> bool {
+    let values = vec!["lane", "track", "opposite_lane", "opposite_track"];
+    tags.is_any(&format!("cycleway:{side}"), values.clone())
+        || tags.is_any("cycleway:both", values.clone())
+        || tags.is_any("cycleway", values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summarize(components: Vec<Component>) -> Vec<String> {
+        components
+            .into_iter()
+            .map(|c| match c.kind {
+                ComponentKind::Lane {
+                    direction, turn, ..
+                } => format!("{direction:?} {}", turn.unwrap_or_default())
+                    .trim()
+                    .to_string(),
+                ComponentKind::Parking => "Parking".to_string(),
+                ComponentKind::CycleLane => "CycleLane".to_string(),
+                _ => "other".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_road_components() {
+        for (input, along, expected) in [
+            (
+                vec![("lanes", "2"), ("parking:both", "lane")],
+                true,
+                vec!["Parking", "Backward", "Forward", "Parking"],
+            ),
+            (
+                vec![
+                    ("lanes:forward", "2"),
+                    ("lanes:backward", "1"),
+                    ("cycleway:right", "lane"),
+                ],
+                true,
+                vec!["Backward", "Forward", "Forward", "CycleLane"],
+            ),
+            (
+                vec![
+                    ("lanes:forward", "2"),
+                    ("lanes:backward", "1"),
+                    ("cycleway:right", "lane"),
+                ],
+                false,
+                vec!["CycleLane", "Backward", "Backward", "Forward"],
+            ),
+            (
+                vec![
+                    ("oneway", "yes"),
+                    ("lanes", "2"),
+                    ("turn:lanes", "left|through"),
+                ],
+                true,
+                vec!["Forward left", "Forward through"],
+            ),
+            (
+                vec![("oneway", "-1"), ("lanes", "2")],
+                false,
+                vec!["Forward", "Forward"],
+            ),
+        ] {
+            let mut tags = Tags::default();
+            for (k, v) in &input {
+                tags.insert(*k, *v);
+            }
+            let got = summarize(road_components(
+                &tags,
+                WayID(1),
+                CarriagewayRole::Main,
+                along,
+            ));
+            assert_eq!(got, expected, "For {input:?} with along={along}");
+        }
+    }
+}
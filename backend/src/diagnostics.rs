@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::dual_carriageway::{DualCarriageway, NotDualCarriageway};
+use crate::roundabout::{NotRoundabout, Roundabout};
+use crate::sidepath::{check_sidepath, NotSidepath};
+use crate::{FaceID, RoadBundler};
+
+/// Explains why bulk operations skip faces
+#[derive(Serialize)]
+pub struct Diagnostics {
+    pub faces: Vec<FaceDiagnostic>,
+    /// How many faces have each problem, keyed by operation and then the problem
+    pub summary: BTreeMap<&'static str, BTreeMap<String, usize>>,
+}
+
+#[derive(Serialize)]
+pub struct FaceDiagnostic {
+    pub face_id: FaceID,
+    /// `None` if the face is a dual carriageway
+    pub dual_carriageway: Option<NotDualCarriageway>,
+    /// `None` if the face is a roundabout
+    pub roundabout: Option<NotRoundabout>,
+    /// `None` if the face has a sidepath
+    pub sidepath: Option<NotSidepath>,
+}
+
+impl RoadBundler {
+    pub fn diagnostics(&self) -> Diagnostics {
+        let mut faces = Vec::new();
+        let mut summary: BTreeMap<&'static str, BTreeMap<String, usize>> = BTreeMap::new();
+        for (id, face) in &self.faces {
+            let dual_carriageway =
+                DualCarriageway::maybe_new(&self.graph, face, Default::default()).err();
+            let roundabout =
+                Roundabout::maybe_new(&self.graph, face, &self.areas.mini_roundabouts).err();
+            let sidepath = check_sidepath(&self.graph, face).err();
+
+            for (operation, problem) in [
+                (
+                    "dual_carriageway",
+                    dual_carriageway.as_ref().map(problem_name),
+                ),
+                ("roundabout", roundabout.as_ref().map(problem_name)),
+                ("sidepath", sidepath.as_ref().map(problem_name)),
+            ] {
+                *summary
+                    .entry(operation)
+                    .or_default()
+                    .entry(problem.unwrap_or_else(|| "Applies".to_string()))
+                    .or_default() += 1;
+            }
+
+            faces.push(FaceDiagnostic {
+                face_id: *id,
+                dual_carriageway,
+                roundabout,
+                sidepath,
+            });
+        }
+        Diagnostics { faces, summary }
+    }
+}
+
+/// The `problem` tag that reasons like `NotDualCarriageway` are serialized with, which is the
+/// name of the variant
+fn problem_name<T: Serialize>(reason: &T) -> String {
+    serde_json::to_value(reason)
+        .ok()
+        .and_then(|value| Some(value.get("problem")?.as_str()?.to_string()))
+        .expect("reasons are serialized with a problem tag")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FaceKind;
+
+    /// The variant name, the part of the Debug output before any fields
+    fn variant_name<T: std::fmt::Debug>(reason: &T) -> String {
+        format!("{reason:?}")
+            .split([' ', '{', '('])
+            .next()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_problem_name() {
+        let kind = FaceKind::UrbanBlock;
+        for reason in [
+            NotDualCarriageway::WrongFaceKind { kind: kind.clone() },
+            NotDualCarriageway::NoOneways,
+            NotDualCarriageway::NotEnoughEdges { edges: Vec::new() },
+            NotDualCarriageway::NotThin { aspect_ratio: 1.0 },
+            NotDualCarriageway::NotOppositeDirections {
+                side1: Vec::new(),
+                side2: Vec::new(),
+            },
+            NotDualCarriageway::SidesNotJoined {
+                side1: Vec::new(),
+                side2: Vec::new(),
+                side1_pieces: 0,
+                side2_pieces: 0,
+            },
+            NotDualCarriageway::NoCenterLine {
+                error: String::new(),
+            },
+        ] {
+            assert_eq!(problem_name(&reason), variant_name(&reason));
+        }
+        for reason in [
+            NotRoundabout::WrongFaceKind { kind: kind.clone() },
+            NotRoundabout::NoEdges,
+            NotRoundabout::NotRing {
+                other_edges: Vec::new(),
+            },
+            NotRoundabout::NotCircular { circularity: 0.0 },
+        ] {
+            assert_eq!(problem_name(&reason), variant_name(&reason));
+        }
+        for reason in [
+            NotSidepath::WrongFaceKind { kind },
+            NotSidepath::NoSidepathEdges {
+                connector_edges: Vec::new(),
+            },
+            NotSidepath::NoParallelRoad {
+                sidepath_edges: Vec::new(),
+                road_edges: Vec::new(),
+            },
+        ] {
+            assert_eq!(problem_name(&reason), variant_name(&reason));
+        }
+    }
+}
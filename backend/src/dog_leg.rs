@@ -1,7 +1,13 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Context, Result};
 use geo::{Euclidean, InterpolatableLine, Length};
 
 use crate::geo_helpers::linestring_bearing;
-use crate::{EdgeID, Intersection, IntersectionProvenance, RoadBundler};
+use crate::{Command, EdgeID, RoadBundler};
+
+/// By default, only edges shorter than this are collapsed by `fix_all_dog_legs`
+pub const DEFAULT_MAX_DOG_LEG_LENGTH: f64 = 5.0;
 
 pub struct DogLeg {
     // In no particular order
@@ -9,42 +15,44 @@ pub struct DogLeg {
 }
 
 impl RoadBundler {
-    pub fn collapse_edge(&mut self, collapse_e: EdgeID) {
+    pub fn collapse_edge(&mut self, collapse_e: EdgeID) -> Result<()> {
         let (src, dst, midpt) = {
-            let edge = &self.graph.edges[&collapse_e];
+            let Some(edge) = self.graph.edges.get(&collapse_e) else {
+                bail!("{collapse_e:?} doesn't exist");
+            };
             let midpt = edge
                 .linestring
                 .point_at_ratio_from_start(&Euclidean, 0.5)
-                .unwrap();
+                .with_context(|| format!("{collapse_e:?} has no midpoint"))?;
             (edge.src, edge.dst, midpt)
         };
-        let dog_leg = self.is_dog_leg(collapse_e);
+        // Longer edges are collapsed like any other, even when they're shaped like a dog-leg
+        let dog_leg = self.is_dog_leg(collapse_e, DEFAULT_MAX_DOG_LEG_LENGTH);
 
-        self.graph.remove_edge(collapse_e);
+        let collapsed_edge = self.graph.remove_edge(collapse_e)?;
 
         // Create a new intersection at the middle of the short edge
-        let new_intersection = self.graph.new_intersection_id();
-        self.graph.intersections.insert(
-            new_intersection,
-            Intersection {
-                id: new_intersection,
-                edges: Vec::new(),
-                point: midpt,
-                provenance: IntersectionProvenance::Synthetic,
-            },
-        );
+        let new_intersection = self.graph.create_new_intersection(midpt);
+        self.graph
+            .intersection_mut(new_intersection)
+            .unwrap()
+            .absorbed_edges
+            .extend(collapsed_edge.kind.all_original_edges());
 
         // Remove the two old intersections, reconnecting the edges
         let extend_geometry = dog_leg.is_none();
         self.graph
-            .replace_intersection(src, new_intersection, extend_geometry);
-        self.graph
-            .replace_intersection(dst, new_intersection, extend_geometry);
+            .replace_intersection(src, new_intersection, extend_geometry)?;
+        // A loop only has one intersection
+        if dst != src {
+            self.graph
+                .replace_intersection(dst, new_intersection, extend_geometry)?;
+        }
 
         if let Some(dog_leg) = dog_leg {
-            for e in &self.graph.intersections[&new_intersection].edges {
-                let fix_edge = self.graph.edges.get_mut(e).unwrap();
-                if *e == dog_leg.side_roads.0 || *e == dog_leg.side_roads.1 {
+            for e in self.graph.intersections[&new_intersection].edges.clone() {
+                let fix_edge = self.graph.edge_mut(e).unwrap();
+                if e == dog_leg.side_roads.0 || e == dog_leg.side_roads.1 {
                     // For the two side roads, trim off the first or last meter, then connect to
                     // the new intersection
                     if fix_edge.src == new_intersection {
@@ -75,16 +83,41 @@ impl RoadBundler {
                 }
             }
         }
+        Ok(())
     }
 
-    pub fn is_dog_leg(&self, e: EdgeID) -> Option<DogLeg> {
-        let edge = &self.graph.edges[&e];
-        if Euclidean.length(&edge.linestring) > 5.0 {
-            return None;
+    /// Returns the number of new commands applied. Edges that fail to collapse are skipped.
+    pub fn fix_all_dog_legs_with_max_length(&mut self, max_length: f64) -> usize {
+        let mut cmds_applied = 0;
+        let mut failed = BTreeSet::new();
+
+        self.start_transaction();
+        while let Some(id) = self
+            .graph
+            .edges
+            .keys()
+            .find(|e| !failed.contains(*e) && self.is_dog_leg(**e, max_length).is_some())
+            .copied()
+        {
+            match self.push_cmd(Command::CollapseEdge(id)) {
+                Ok(()) => cmds_applied += 1,
+                Err(err) => {
+                    warn!("Skipping dog-leg {id:?}: {err}");
+                    failed.insert(id);
+                }
+            }
         }
-        if edge.kind.get_road_name(&self.graph).is_none() {
+        self.finish_transaction();
+
+        cmds_applied
+    }
+
+    pub fn is_dog_leg(&self, e: EdgeID, max_length: f64) -> Option<DogLeg> {
+        let edge = &self.graph.edges[&e];
+        if Euclidean.length(&edge.linestring) > max_length {
             return None;
         }
+        edge.kind.get_road_name(&self.graph)?;
         let mut src_edges = self.graph.intersections[&edge.src].edges.clone();
         let mut dst_edges = self.graph.intersections[&edge.dst].edges.clone();
         if src_edges.len() != 3 || dst_edges.len() != 3 {
@@ -132,3 +165,43 @@ impl RoadBundler {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use geo::Coord;
+
+    use super::*;
+    use crate::test_helpers::{edge_near, TestMap};
+
+    #[test]
+    fn test_collapse_edge_trims_only_short_dog_legs() {
+        for (length, trimmed) in [(4.0, true), (20.0, false)] {
+            // Side Street jogs along Main Street, leaving north and south
+            let mut b = TestMap::default()
+                .road(&[(0.0, 0.0), (100.0, 0.0)], "Main Street")
+                .road(&[(100.0, 0.0), (100.0 + length, 0.0)], "Main Street")
+                .road(&[(100.0 + length, 0.0), (200.0, 0.0)], "Main Street")
+                .road(&[(100.0, -80.0), (100.0, 0.0)], "Side Street")
+                .road(
+                    &[(100.0 + length, 0.0), (100.0 + length, 80.0)],
+                    "Side Street",
+                )
+                .build();
+            let e = edge_near(&b, 100.0 + length / 2.0, 0.0);
+            let side_road = edge_near(&b, 100.0, -40.0);
+            assert!(b.is_dog_leg(e, length).is_some());
+            b.do_cmd(Command::CollapseEdge(e)).unwrap();
+
+            // Trimming replaces the side road's old endpoint; otherwise it's extended
+            let old_endpoint = Coord { x: 100.0, y: 0.0 };
+            assert_eq!(
+                !b.graph.edges[&side_road]
+                    .linestring
+                    .0
+                    .contains(&old_endpoint),
+                trimmed,
+                "for a {length}m edge"
+            );
+        }
+    }
+}
@@ -1,33 +1,150 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 
-use anyhow::{Context, Result};
-use geo::{Distance, Euclidean, LineString, Point};
+use anyhow::Result;
+use geo::{
+    unary_union, Area, BooleanOps, Centroid, ConvexHull, Distance, Euclidean, Length, LineString,
+    MultiPoint, Point, Polygon,
+};
 use geojson::GeoJson;
 use itertools::Itertools;
-use serde::Serialize;
-use utils::{collapse_degree_2, Debugger, KeyedLineString};
+use serde::{Deserialize, Serialize};
+use utils::{collapse_degree_2, Debugger, KeyedLineString, Tags};
 
-use crate::geo_helpers::{average_linestrings, linestring_bearing};
+use crate::geo_helpers::{
+    average_linestrings, fraction_range, linestring_bearing, long_axis_bearing, roughly_parallel,
+    skeleton_linestrings,
+};
+use crate::graph::{Edge, OriginalEdgeID};
 use crate::split_line::Splits;
 use crate::{
-    EdgeID, EdgeKind, Face, FaceID, FaceKind, Graph, Intersection, IntersectionID,
-    IntersectionProvenance, RoadBundler,
+    Command, EdgeID, EdgeKind, Face, FaceID, FaceKind, Graph, IntersectionID, RoadBundler,
 };
 
 // TODO Don't serialize this. Plumb the extra debug info as foreign members?
 #[derive(Serialize)]
 pub struct DualCarriageway {
-    pub name: String,
+    /// The name or ref shared by both carriageways, depending on `signal`
+    pub name: Option<String>,
+    pub signal: DcSignal,
     pub center_line: LineString,
+    /// Every carriageway bundled into this road, starting with the two main ones
+    pub carriageways: Vec<Carriageway>,
     #[serde(skip)]
     pub splits: Splits,
 
     pub debug_hover: GeoJson,
 }
 
+#[derive(Serialize)]
+pub struct Carriageway {
+    pub role: CarriagewayRole,
+    pub edges: Vec<EdgeID>,
+}
+
+/// What a carriageway bundled into a DC is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum CarriagewayRole {
+    /// One of the oneways defining the DC, or another carriageway of the same road
+    Main,
+    /// A frontage or service road alongside
+    Service,
+    Bus,
+    Other,
+}
+
+/// How to build the center line between the two carriageways
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CenterLineMethod {
+    /// Walk along one side and average with the closest point on the other
+    #[default]
+    Average,
+    /// Use the skeleton of the corridor between the two sides. Better on curves and when the
+    /// sides are different lengths.
+    Skeleton,
+}
+
+/// How the carriageways of a DC were found
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum DcSignal {
+    /// Oneway edges with the same name
+    Name,
+    /// Oneway edges with the same ref, without a shared name
+    Ref,
+    /// Oneway edges pointing opposite ways around a long, thin face, regardless of their tags
+    Geometry,
+}
+
+/// A face's length divided by its width, if it were a rectangle. Faces detected as DCs only by
+/// geometry must be at least this thin.
+const MIN_DC_ASPECT_RATIO: f64 = 3.0;
+
+/// Why a face isn't a dual carriageway
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "problem")]
+pub enum NotDualCarriageway {
+    WrongFaceKind {
+        kind: FaceKind,
+    },
+    NoOneways,
+    NotEnoughEdges {
+        edges: Vec<EdgeID>,
+    },
+    /// Only checked when the oneways don't share a name or ref
+    NotThin {
+        aspect_ratio: f64,
+    },
+    /// Only checked when the oneways don't share a name or ref
+    NotOppositeDirections {
+        side1: Vec<EdgeID>,
+        side2: Vec<EdgeID>,
+    },
+    SidesNotJoined {
+        side1: Vec<EdgeID>,
+        side2: Vec<EdgeID>,
+        side1_pieces: usize,
+        side2_pieces: usize,
+    },
+    NoCenterLine {
+        error: String,
+    },
+}
+
+impl fmt::Display for NotDualCarriageway {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::WrongFaceKind { .. } => write!(f, "Face isn't a road artifact"),
+            Self::NoOneways => write!(f, "no oneways"),
+            Self::NotEnoughEdges { .. } => write!(f, "not enough edges to form a DC"),
+            Self::NotThin { aspect_ratio } => write!(
+                f,
+                "oneways have no common name or ref, and the face isn't thin (aspect ratio {aspect_ratio:.1})"
+            ),
+            Self::NotOppositeDirections { .. } => write!(
+                f,
+                "oneways have no common name or ref, and don't point in opposite directions"
+            ),
+            Self::SidesNotJoined {
+                side1_pieces,
+                side2_pieces,
+                ..
+            } => write!(
+                f,
+                "Not a DC because we have {side1_pieces} and {side2_pieces} joined line results"
+            ),
+            Self::NoCenterLine { error } => write!(f, "couldn't make a center line: {error}"),
+        }
+    }
+}
+
 impl DualCarriageway {
-    pub fn maybe_new(graph: &Graph, face: &Face) -> Result<Self> {
-        let (name, dc_edges) = detect_dc_edges(graph, face)?;
+    pub fn maybe_new(
+        graph: &Graph,
+        face: &Face,
+        method: CenterLineMethod,
+    ) -> Result<Self, NotDualCarriageway> {
+        let (signal, name, dc_edges) = detect_dc_edges(graph, face)?;
 
         let mut edge_bearings: Vec<(EdgeID, f64)> = dc_edges
             .into_iter()
@@ -39,11 +156,27 @@ impl DualCarriageway {
 
         let mut side1 = Vec::new();
         let mut side2 = Vec::new();
-        for ((e, _), class) in edge_bearings.into_iter().zip(classes.into_iter()) {
+        let mut side1_bearing = None;
+        let mut side2_bearing = None;
+        for ((e, bearing), class) in edge_bearings.into_iter().zip(classes) {
             if class == 0 {
                 side1.push(e);
+                side1_bearing.get_or_insert(bearing);
             } else {
                 side2.push(e);
+                side2_bearing.get_or_insert(bearing);
+            }
+        }
+
+        // Tags already suggest these belong together, but without them, make sure the two sides
+        // point opposite ways
+        if signal == DcSignal::Geometry {
+            let opposite = match (side1_bearing, side2_bearing) {
+                (Some(b1), Some(b2)) => ((b1 - b2).abs() % 360.0 - 180.0).abs() < 30.0,
+                _ => false,
+            };
+            if !opposite {
+                return Err(NotDualCarriageway::NotOppositeDirections { side1, side2 });
             }
         }
 
@@ -68,16 +201,29 @@ impl DualCarriageway {
                 .collect(),
         );
         if side1_joined.len() != 1 || side2_joined.len() != 1 {
-            bail!(
-                "Not a DC because we have {} and {} joined line results",
-                side1_joined.len(),
-                side2_joined.len()
-            );
+            return Err(NotDualCarriageway::SidesNotJoined {
+                side1_pieces: side1_joined.len(),
+                side2_pieces: side2_joined.len(),
+                side1,
+                side2,
+            });
         }
 
-        let center_line =
-            average_linestrings(&side1_joined[0].linestring, &side2_joined[0].linestring)?;
-        let splits = crate::split_line::split_center(graph, &center_line, face);
+        let ls1 = &side1_joined[0].linestring;
+        let ls2 = &side2_joined[0].linestring;
+        let carriageways = find_carriageways(graph, face, &name, &side1, &side2, ls1);
+        let center_line = match method {
+            CenterLineMethod::Average => average_linestrings(ls1, ls2),
+            CenterLineMethod::Skeleton => skeleton_linestrings(ls1, ls2),
+        }
+        .map_err(|err| NotDualCarriageway::NoCenterLine {
+            error: err.to_string(),
+        })?;
+        let splits = crate::split_line::split_center(graph, &center_line, face).map_err(|err| {
+            NotDualCarriageway::NoCenterLine {
+                error: err.to_string(),
+            }
+        })?;
 
         let mut debug_hover = Debugger::new(graph.mercator.clone());
         for e in &side1 {
@@ -112,49 +258,253 @@ impl DualCarriageway {
         for pt in &splits.new_endpts {
             debug_hover.circle(*pt, "split", "green", 5);
         }
+        for carriageway in carriageways.iter().skip(2) {
+            for e in &carriageway.edges {
+                debug_hover.line(
+                    &graph.edges[e].linestring,
+                    &format!("{:?} carriageway", carriageway.role),
+                    "orange",
+                    5,
+                    1.0,
+                );
+            }
+        }
 
         Ok(Self {
             name,
+            signal,
             center_line,
+            carriageways,
             splits,
             debug_hover: debug_hover.build(),
         })
     }
 }
 
-fn detect_dc_edges(graph: &Graph, face: &Face) -> Result<(String, Vec<EdgeID>)> {
+/// Besides the two main sides, finds other carriageways running parallel to them
+fn find_carriageways(
+    graph: &Graph,
+    face: &Face,
+    name: &Option<String>,
+    side1: &[EdgeID],
+    side2: &[EdgeID],
+    side1_line: &LineString,
+) -> Vec<Carriageway> {
+    let bearing = linestring_bearing(side1_line);
+    let extra = collapse_degree_2(
+        face.boundary_edges
+            .iter()
+            .chain(&face.internal_edges)
+            .filter(|e| !side1.contains(e) && !side2.contains(e) && is_carriageway(graph, e))
+            .filter(|e| {
+                // The carriageways might point either way
+                let diff = (linestring_bearing(&graph.edges[e].linestring) - bearing).abs() % 180.0;
+                diff.min(180.0 - diff) < 30.0
+            })
+            .map(|e| KeyedLineString {
+                linestring: graph.edges[e].linestring.clone(),
+                ids: vec![(*e, true)],
+                key: carriageway_role(graph, &graph.edges[e].kind, name),
+            })
+            .collect(),
+    );
+
+    let mut carriageways = vec![
+        Carriageway {
+            role: CarriagewayRole::Main,
+            edges: side1.to_vec(),
+        },
+        Carriageway {
+            role: CarriagewayRole::Main,
+            edges: side2.to_vec(),
+        },
+    ];
+    for line in extra {
+        carriageways.push(Carriageway {
+            role: line.key,
+            edges: line.ids.into_iter().map(|(e, _)| e).collect(),
+        });
+    }
+    carriageways
+}
+
+/// Is this edge part of a driveable road?
+fn is_carriageway(graph: &Graph, e: &EdgeID) -> bool {
+    match &graph.edges[e].kind {
+        EdgeKind::Motorized {
+            roads,
+            service_roads,
+            ..
+        } => !roads.is_empty() || !service_roads.is_empty(),
+        EdgeKind::Nonmotorized(_) => false,
+    }
+}
+
+fn carriageway_role(graph: &Graph, kind: &EdgeKind, name: &Option<String>) -> CarriagewayRole {
+    let EdgeKind::Motorized {
+        roads,
+        service_roads,
+        ..
+    } = kind
+    else {
+        return CarriagewayRole::Other;
+    };
+    let roles: BTreeSet<_> = roads
+        .iter()
+        .map(|e| (e, false))
+        .chain(service_roads.iter().map(|e| (e, true)))
+        .map(|(e, service_road)| {
+            original_carriageway_role(&graph.original_edges[e].tags, service_road, name.as_ref())
+        })
+        .collect();
+    if roles.len() == 1 {
+        roles.into_iter().next().unwrap()
+    } else {
+        CarriagewayRole::Other
+    }
+}
+
+/// The role of one original road bundled into a road with this name or ref
+pub fn original_carriageway_role(
+    tags: &Tags,
+    service_road: bool,
+    name: Option<&String>,
+) -> CarriagewayRole {
+    if is_bus_only(tags) {
+        return CarriagewayRole::Bus;
+    }
+    if service_road {
+        return CarriagewayRole::Service;
+    }
+    if name.is_some() && (tags.get("name") == name || tags.get("ref") == name) {
+        return CarriagewayRole::Main;
+    }
+    CarriagewayRole::Other
+}
+
+/// The name or ref shared by every road bundled into an edge, like `DualCarriageway::name`.
+/// Bus-only carriageways are often unnamed, so they don't count.
+pub fn main_road_name<'a>(graph: &'a Graph, kind: &EdgeKind) -> Option<&'a String> {
+    let EdgeKind::Motorized { roads, .. } = kind else {
+        return None;
+    };
+    let tags: Vec<&Tags> = roads
+        .iter()
+        .map(|e| &graph.original_edges[e].tags)
+        .filter(|tags| !is_bus_only(tags))
+        .collect();
+    ["name", "ref"].into_iter().find_map(|key| {
+        let values: BTreeSet<Option<&String>> = tags.iter().map(|tags| tags.get(key)).collect();
+        if values.len() == 1 {
+            values.into_iter().next().unwrap()
+        } else {
+            None
+        }
+    })
+}
+
+fn is_bus_only(tags: &Tags) -> bool {
+    tags.is("highway", "busway")
+        || (tags.is_any("access", vec!["no", "private"])
+            && (tags.is_any("bus", vec!["yes", "designated"])
+                || tags.is_any("psv", vec!["yes", "designated"])))
+}
+
+fn detect_dc_edges(
+    graph: &Graph,
+    face: &Face,
+) -> Result<(DcSignal, Option<String>, Vec<EdgeID>), NotDualCarriageway> {
     if face.kind != FaceKind::RoadArtifact {
-        bail!("Face isn't a road artifact");
+        return Err(NotDualCarriageway::WrongFaceKind {
+            kind: face.kind.clone(),
+        });
     }
 
-    // Find all of the oneway edges with a name
+    // Extra carriageways in the middle of the face might be the main ones
     let oneways: Vec<EdgeID> = face
         .boundary_edges
         .iter()
-        .filter(|e| {
-            graph.edges[e].kind.is_oneway_road(graph)
-                && graph.edges[e].kind.get_road_name(graph).is_some()
-        })
+        .chain(&face.internal_edges)
+        .filter(|e| graph.edges[e].kind.is_oneway_road(graph))
         .cloned()
         .collect();
+    if oneways.is_empty() {
+        return Err(NotDualCarriageway::NoOneways);
+    }
 
-    // Group by their name
-    let oneways_by_name = oneways
-        .into_iter()
-        .into_group_map_by(|e| graph.edges[e].kind.get_road_name(graph).unwrap());
+    // Prefer oneways sharing a name, then a ref
+    for (signal, key) in [(DcSignal::Name, "name"), (DcSignal::Ref, "ref")] {
+        // Group by the tag, then pick the group with the most members
+        if let Some((value, dc_edges)) = oneways
+            .iter()
+            .filter_map(|e| {
+                graph.edges[e]
+                    .kind
+                    .get_road_tag(graph, key)
+                    .map(|value| (value, *e))
+            })
+            .into_group_map()
+            .into_iter()
+            .max_by_key(|(_, list)| list.len())
+        {
+            // Make sure we have at least two edges
+            if dc_edges.len() >= 2 {
+                return Ok((signal, Some(value.to_string()), dc_edges));
+            }
+        }
+    }
 
-    // Pick the group with the most members
-    let (name, dc_edges) = oneways_by_name
-        .into_iter()
-        .max_by_key(|(_, list)| list.len())
-        .context("no oneways")?;
+    // Otherwise use the oneways running along the face, if it looks like the space between two
+    // carriageways. Oneways across it, like links between the carriageways, aren't either side.
+    let aspect_ratio = aspect_ratio(&face.polygon);
+    if aspect_ratio < MIN_DC_ASPECT_RATIO {
+        return Err(NotDualCarriageway::NotThin { aspect_ratio });
+    }
+    let oneways: Vec<EdgeID> = match long_axis_bearing(&face.polygon) {
+        Some(axis) => oneways
+            .into_iter()
+            .filter(|e| roughly_parallel(linestring_bearing(&graph.edges[e].linestring), axis))
+            .collect(),
+        None => oneways,
+    };
+    if oneways.len() < 2 {
+        return Err(NotDualCarriageway::NotEnoughEdges { edges: oneways });
+    }
+    Ok((DcSignal::Geometry, None, oneways))
+}
 
-    // Make sure we have at least two edges
-    if dc_edges.len() < 2 {
-        bail!("not enough edges to form a DC");
+/// Treats the polygon as a rectangle with the same area and perimeter, and returns its length
+/// divided by its width
+fn aspect_ratio(polygon: &Polygon) -> f64 {
+    let (length, width) = equivalent_rectangle(polygon);
+    if length == width {
+        return 1.0;
+    }
+    if width <= 0.0 {
+        return f64::INFINITY;
     }
+    length / width
+}
+
+/// The longer side of the polygon, if it were a rectangle with the same area and perimeter
+fn rectangle_length(polygon: &Polygon) -> f64 {
+    equivalent_rectangle(polygon).0
+}
 
-    Ok((name.to_string(), dc_edges))
+/// Returns the length and width of a rectangle with the same area and perimeter
+fn equivalent_rectangle(polygon: &Polygon) -> (f64, f64) {
+    let perimeter = Euclidean.length(polygon.exterior());
+    let area = polygon.unsigned_area();
+    // The sides are the roots of x^2 - (perimeter / 2) x + area
+    let discriminant = perimeter.powi(2) - 16.0 * area;
+    if discriminant <= 0.0 {
+        // Not possible for a rectangle; treat it as a square
+        return (perimeter / 4.0, perimeter / 4.0);
+    }
+    (
+        (perimeter + discriminant.sqrt()) / 4.0,
+        (perimeter - discriminant.sqrt()) / 4.0,
+    )
 }
 
 // Assumes input is sorted
@@ -182,24 +532,156 @@ fn classify_bearings(bearings: Vec<f64>) -> Vec<usize> {
 }
 
 impl RoadBundler {
-    pub fn collapse_dual_carriageway(&mut self, id: FaceID) {
+    /// Returns the number of new commands applied. Faces that fail to collapse are skipped.
+    pub fn fix_all_dual_carriageways(&mut self, method: CenterLineMethod) -> usize {
+        let mut cmds_applied = 0;
+        let mut failed = BTreeSet::new();
+
+        self.start_transaction();
+        loop {
+            let faces_per_edge = faces_per_edge(&self.faces);
+            if let Some(id) = self.faces.keys().find_map(|id| {
+                (!failed.contains(id) && self.is_dual_carriageway(&faces_per_edge, *id, method))
+                    .then_some(*id)
+            }) {
+                match self.push_cmd(Command::collapse_dual_carriageway(id, method)) {
+                    Ok(()) => cmds_applied += 1,
+                    Err(err) => {
+                        warn!("Skipping dual carriageway {id:?}: {err}");
+                        failed.insert(id);
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+        self.finish_transaction();
+
+        cmds_applied
+    }
+
+    /// Checks if a face can be collapsed as a DC, alone or along with extra carriageways beside
+    /// it. If not, explains why the face alone isn't a DC.
+    pub fn check_dual_carriageway(
+        &self,
+        id: FaceID,
+        method: CenterLineMethod,
+    ) -> Result<(), NotDualCarriageway> {
+        match DualCarriageway::maybe_new(&self.graph, &self.faces[&id], method) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                let faces_per_edge = faces_per_edge(&self.faces);
+                if self.is_dual_carriageway(&faces_per_edge, id, method) {
+                    Ok(())
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    fn is_dual_carriageway(
+        &self,
+        faces_per_edge: &BTreeMap<EdgeID, Vec<FaceID>>,
+        id: FaceID,
+        method: CenterLineMethod,
+    ) -> bool {
         let face = &self.faces[&id];
-        let dc = crate::dual_carriageway::DualCarriageway::maybe_new(&self.graph, face)
-            .expect("collapse_dual_carriageway on something that isn't a DC");
+        if face.kind != FaceKind::RoadArtifact {
+            return false;
+        }
+        if DualCarriageway::maybe_new(&self.graph, face, method).is_ok() {
+            return true;
+        }
+        // Faces beside extra carriageways are long strips. Skip the expensive check otherwise.
+        aspect_ratio(&face.polygon) >= MIN_DC_ASPECT_RATIO
+            && merge_dc_faces(&self.graph, &self.faces, faces_per_edge, id, method, true).is_some()
+    }
 
-        // Remove all the boundary_edges
-        for e in &face.boundary_edges {
-            self.graph.remove_edge(*e);
+    pub fn collapse_dual_carriageway(
+        &mut self,
+        id: FaceID,
+        method: CenterLineMethod,
+    ) -> Result<()> {
+        let Some(face) = self.faces.get(&id) else {
+            bail!("{id:?} doesn't exist");
+        };
+        if let Err(err) = self.check_dual_carriageway(id, method) {
+            bail!("{id:?} isn't a dual carriageway: {err}");
+        }
+        // Collapse all the faces of this road at once, if possible. If extra carriageways
+        // alongside it spoil things, just follow the road.
+        let faces_per_edge = faces_per_edge(&self.faces);
+        let merge = |across| {
+            merge_dc_faces(
+                &self.graph,
+                &self.faces,
+                &faces_per_edge,
+                id,
+                method,
+                across,
+            )
+        };
+        let (face, gap_edges) = merge(true)
+            .or_else(|| merge(false))
+            .unwrap_or_else(|| (face.clone(), Vec::new()));
+        let face = &face;
+        let dc = match DualCarriageway::maybe_new(&self.graph, face, method) {
+            Ok(dc) => dc,
+            Err(err) => bail!("{id:?} isn't a dual carriageway: {err}"),
+        };
+
+        // Remove all the boundary_edges, every carriageway, and the edges crossing between them
+        let mut removed_edges: BTreeSet<EdgeID> = face.boundary_edges.iter().cloned().collect();
+        removed_edges.extend(gap_edges);
+        let mut carriageway_edges = BTreeSet::new();
+        for carriageway in &dc.carriageways {
+            carriageway_edges.extend(carriageway.edges.iter().cloned());
+        }
+        removed_edges.extend(carriageway_edges.iter().cloned());
+
+        // Only the carriageways become the new center line. Side roads and U-turn gaps crossing
+        // between them are handed to the stubs below, or absorbed by the new intersections.
+        let kinds = assign_kinds_to_pieces(&self.graph, &carriageway_edges, &dc);
+        let mut crossing_edges: Vec<Edge> = removed_edges
+            .difference(&carriageway_edges)
+            .map(|e| self.graph.edges[e].clone())
+            .collect();
+        let mut carriageway_pieces: BTreeMap<IntersectionID, BTreeSet<OriginalEdgeID>> =
+            BTreeMap::new();
+        for e in &carriageway_edges {
+            let edge = &self.graph.edges[e];
+            // Sidepaths along the carriageways stay with the center line
+            let EdgeKind::Motorized { roads, .. } = &edge.kind else {
+                continue;
+            };
+            for i in [edge.src, edge.dst] {
+                carriageway_pieces
+                    .entry(i)
+                    .or_default()
+                    .extend(roads.iter().cloned());
+            }
+        }
+
+        for e in &removed_edges {
+            self.graph.remove_edge(*e)?;
         }
 
         // Create the new split center-lines, with new intersections
-        let new_intersections =
-            create_new_linked_edges(&mut self.graph, dc.splits.lines, dc.splits.new_endpts);
+        let new_intersections = create_new_linked_edges(
+            &mut self.graph,
+            dc.splits.lines,
+            dc.splits.new_endpts,
+            kinds,
+        )?;
 
         // Re-attach every connecting edge to the nearest new intersection
         // (we could maybe preserve more info to do this directly?)
+        let mut reattached_points: BTreeMap<IntersectionID, Vec<Point>> = BTreeMap::new();
         for e in &face.connecting_edges {
-            let edge = &self.graph.edges[e];
+            let Some(edge) = self.graph.edges.get(e) else {
+                bail!("connecting edge {e:?} doesn't exist");
+            };
 
             // There could be a loop; handle each endpoint if needed
             for existing_i in [edge.src, edge.dst] {
@@ -208,13 +690,20 @@ impl RoadBundler {
                 }
                 let existing_pt = self.graph.intersections[&existing_i].point;
 
-                let closest_new_i = *new_intersections
-                    .iter()
-                    .min_by_key(|i| {
-                        (10e6 * Euclidean.distance(existing_pt, self.graph.intersections[i].point))
-                            as usize
-                    })
-                    .unwrap();
+                let closest_new_i =
+                    closest_intersection(&self.graph, &new_intersections, existing_pt);
+                reattached_points
+                    .entry(closest_new_i)
+                    .or_default()
+                    .push(existing_pt);
+                let kind = stub_kind(
+                    existing_i,
+                    &mut crossing_edges,
+                    carriageway_pieces
+                        .get(&existing_i)
+                        .cloned()
+                        .unwrap_or_default(),
+                );
                 self.graph.create_new_edge(
                     LineString::new(vec![
                         existing_pt.into(),
@@ -222,79 +711,458 @@ impl RoadBundler {
                     ]),
                     existing_i,
                     closest_new_i,
-                    // TODO Can we associate this with any original edges at all?
-                    EdgeKind::Motorized {
-                        roads: BTreeSet::new(),
-                        service_roads: BTreeSet::new(),
-                        sidepaths: BTreeSet::new(),
-                        connectors: BTreeSet::new(),
-                    },
-                );
+                    kind,
+                )?;
             }
         }
 
-        // Remove orphaned intersections
+        // Crossings that no side road continues, like U-turn gaps, become part of the nearest
+        // new intersection
+        for edge in crossing_edges {
+            let Some(middle) = edge.linestring.centroid() else {
+                continue;
+            };
+            let closest_new_i = closest_intersection(&self.graph, &new_intersections, middle);
+            self.graph
+                .intersection_mut(closest_new_i)
+                .unwrap()
+                .absorbed_edges
+                .extend(edge.kind.all_original_edges());
+        }
+
+        // Each new intersection where connecting edges meet covers the part of the face between
+        // them
+        for (new_i, mut points) in reattached_points {
+            let intersection = self.graph.intersection_mut(new_i).unwrap();
+            points.push(intersection.point);
+            let footprint = face
+                .polygon
+                .intersection(&MultiPoint::new(points).convex_hull());
+            if footprint.unsigned_area() > 0.0 {
+                intersection.add_footprint(footprint);
+            }
+        }
+
+        // Remove orphaned intersections, remembering them in the nearest new intersection
         for i in &face.boundary_intersections {
             if self.graph.intersections[i].edges.is_empty() {
-                self.graph.remove_empty_intersection(*i);
+                let orphan = self.graph.remove_empty_intersection(*i)?;
+                let closest_new_i =
+                    closest_intersection(&self.graph, &new_intersections, orphan.point);
+                self.graph
+                    .intersection_mut(closest_new_i)
+                    .unwrap()
+                    .absorb(orphan);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `candidates` must be non-empty
+fn closest_intersection(graph: &Graph, candidates: &[IntersectionID], pt: Point) -> IntersectionID {
+    *candidates
+        .iter()
+        .min_by_key(|i| (10e6 * Euclidean.distance(pt, graph.intersections[i].point)) as usize)
+        .unwrap()
+}
+
+/// A dual carriageway is split into many faces. Along the road, every side road or U-turn gap
+/// crossing between the carriageways splits it. Across the road, any extra carriageway in the
+/// middle or alongside it, like a bus-only road or a frontage road, splits it. Starting from one
+/// face, find all of these faces belonging to the same road. Returns the faces merged into one,
+/// with the extra carriageways as internal edges, and the gap edges between faces. Returns `None`
+/// if there's just one face or the merged face isn't a DC.
+fn merge_dc_faces(
+    graph: &Graph,
+    faces: &BTreeMap<FaceID, Face>,
+    faces_per_edge: &BTreeMap<EdgeID, Vec<FaceID>>,
+    start: FaceID,
+    method: CenterLineMethod,
+    across: bool,
+) -> Option<(Face, Vec<EdgeID>)> {
+    let road = |face: &Face| {
+        DualCarriageway::maybe_new(graph, face, method)
+            .ok()
+            .map(|dc| (dc.signal, dc.name))
+    };
+    let parallel = |id: FaceID| {
+        if across {
+            parallel_faces(graph, faces, faces_per_edge, id)
+        } else {
+            (BTreeSet::from([id]), BTreeSet::new())
+        }
+    };
+
+    let (mut chain, mut carriageways) = parallel(start);
+    let start_road = road(&merge_faces(faces, &chain, &carriageways)?.0)?;
+
+    let mut queue: Vec<FaceID> = chain.iter().cloned().collect();
+    while let Some(current) = queue.pop() {
+        for e in &faces[&current].boundary_edges {
+            // The carriageways themselves are oneway
+            if carriageways.contains(e) || graph.edges[e].kind.is_oneway_road(graph) {
+                continue;
+            }
+            for next in faces_per_edge.get(e).into_iter().flatten() {
+                if chain.contains(next) {
+                    continue;
+                }
+                let (next_faces, next_carriageways) = parallel(*next);
+                if next_faces.iter().any(|id| chain.contains(id)) {
+                    continue;
+                }
+                let Some((merged, _)) = merge_faces(faces, &next_faces, &next_carriageways) else {
+                    continue;
+                };
+                if road(&merged).as_ref() == Some(&start_road) {
+                    queue.extend(next_faces.iter().cloned());
+                    chain.extend(next_faces);
+                    carriageways.extend(next_carriageways);
+                }
+            }
+        }
+    }
+    if chain.len() == 1 {
+        return None;
+    }
+
+    let (merged, gap_edges) = merge_faces(faces, &chain, &carriageways)?;
+    // The carriageways might not join up across the whole chain
+    if road(&merged) != Some(start_road) {
+        return None;
+    }
+    Some((merged, gap_edges))
+}
+
+/// Which road artifact faces each edge bounds
+fn faces_per_edge(faces: &BTreeMap<FaceID, Face>) -> BTreeMap<EdgeID, Vec<FaceID>> {
+    let mut faces_per_edge: BTreeMap<EdgeID, Vec<FaceID>> = BTreeMap::new();
+    for (id, face) in faces {
+        if face.kind == FaceKind::RoadArtifact {
+            for e in &face.boundary_edges {
+                faces_per_edge.entry(*e).or_default().push(*id);
+            }
+        }
+    }
+    faces_per_edge
+}
+
+/// Finds the faces either side of extra carriageways running alongside this one. They must be
+/// thin and share a long edge. Returns the faces and the carriageway edges between them.
+fn parallel_faces(
+    graph: &Graph,
+    faces: &BTreeMap<FaceID, Face>,
+    faces_per_edge: &BTreeMap<EdgeID, Vec<FaceID>>,
+    start: FaceID,
+) -> (BTreeSet<FaceID>, BTreeSet<EdgeID>) {
+    let mut group = BTreeSet::from([start]);
+    let mut carriageways = BTreeSet::new();
+    let mut queue = vec![start];
+    while let Some(current) = queue.pop() {
+        let face = &faces[&current];
+        for e in &face.boundary_edges {
+            if !is_carriageway(graph, e) {
+                continue;
+            }
+            for next in faces_per_edge.get(e).into_iter().flatten() {
+                if *next == current {
+                    continue;
+                }
+                if group.contains(next) {
+                    carriageways.insert(*e);
+                    continue;
+                }
+                let next_face = &faces[next];
+                if aspect_ratio(&next_face.polygon) < MIN_DC_ASPECT_RATIO {
+                    continue;
+                }
+                let shared_length: f64 = face
+                    .boundary_edges
+                    .iter()
+                    .filter(|x| next_face.boundary_edges.contains(x))
+                    .map(|x| Euclidean.length(&graph.edges[x].linestring))
+                    .sum();
+                let length =
+                    rectangle_length(&face.polygon).min(rectangle_length(&next_face.polygon));
+                if shared_length >= 0.5 * length {
+                    group.insert(*next);
+                    carriageways.insert(*e);
+                    queue.push(*next);
+                }
             }
         }
     }
+    (group, carriageways)
+}
+
+/// Merges adjacent faces into one. Edges shared between the faces become internal edges if
+/// they're in `carriageways`, or are returned as gap edges otherwise.
+fn merge_faces(
+    faces: &BTreeMap<FaceID, Face>,
+    ids: &BTreeSet<FaceID>,
+    carriageways: &BTreeSet<EdgeID>,
+) -> Option<(Face, Vec<EdgeID>)> {
+    if ids.len() == 1 {
+        return Some((faces[ids.first().unwrap()].clone(), Vec::new()));
+    }
+
+    // The faces are all the same kind
+    let kind = faces[ids.first()?].kind.clone();
+    let union = unary_union(ids.iter().map(|id| &faces[id].polygon));
+    let [polygon] = union.0.as_slice() else {
+        return None;
+    };
+
+    let mut boundary_counts: BTreeMap<EdgeID, usize> = BTreeMap::new();
+    let mut boundary_intersections = BTreeSet::new();
+    let mut connecting_edges = BTreeSet::new();
+    let mut internal_edges = BTreeSet::new();
+    for id in ids {
+        let face = &faces[id];
+        for e in &face.boundary_edges {
+            *boundary_counts.entry(*e).or_default() += 1;
+        }
+        boundary_intersections.extend(face.boundary_intersections.iter().cloned());
+        connecting_edges.extend(face.connecting_edges.iter().cloned());
+        internal_edges.extend(face.internal_edges.iter().cloned());
+    }
+    let mut boundary_edges = Vec::new();
+    let mut gap_edges = Vec::new();
+    for (e, count) in boundary_counts {
+        if count == 1 {
+            boundary_edges.push(e);
+        } else if carriageways.contains(&e) {
+            internal_edges.insert(e);
+        } else {
+            gap_edges.push(e);
+        }
+    }
+    connecting_edges.retain(|e| {
+        !boundary_edges.contains(e) && !gap_edges.contains(e) && !internal_edges.contains(e)
+    });
+
+    let merged = Face {
+        polygon: polygon.clone(),
+        kind,
+        boundary_edges,
+        boundary_intersections: boundary_intersections.into_iter().collect(),
+        connecting_edges: connecting_edges.into_iter().collect(),
+        internal_edges: internal_edges.into_iter().collect(),
+    };
+    Some((merged, gap_edges))
+}
+
+/// Each carriageway edge gets merged into the one piece of the split center line that it overlaps
+/// the most, or is closest to. Returns one kind per piece.
+fn assign_kinds_to_pieces(
+    graph: &Graph,
+    carriageway_edges: &BTreeSet<EdgeID>,
+    dc: &DualCarriageway,
+) -> Vec<EdgeKind> {
+    let piece_ranges: Vec<(f64, f64)> = dc
+        .splits
+        .lines
+        .iter()
+        .map(|ls| fraction_range(&dc.center_line, ls))
+        .collect();
+    let mut kinds = vec![
+        EdgeKind::Motorized {
+            roads: BTreeSet::new(),
+            service_roads: BTreeSet::new(),
+            sidepaths: BTreeSet::new(),
+            sidepath_positions: BTreeMap::new(),
+            connectors: BTreeSet::new(),
+        };
+        piece_ranges.len()
+    ];
+
+    for e in carriageway_edges {
+        let edge = &graph.edges[e];
+        let (start, end) = fraction_range(&dc.center_line, &edge.linestring);
+        // When the ranges don't overlap, this is the negative gap between them
+        let Some(idx) = (0..piece_ranges.len()).max_by_key(|idx| {
+            let (piece_start, piece_end) = piece_ranges[*idx];
+            ((end.min(piece_end) - start.max(piece_start)) * 10e6) as isize
+        }) else {
+            continue;
+        };
+        // Nonmotorized edges can't be merged, but they shouldn't be carriageways
+        if let Some(kind) = kinds[idx].merge(&edge.kind) {
+            kinds[idx] = kind;
+        }
+    }
+
+    kinds
+}
+
+/// The stub reconnecting a side road at `existing_i` to the new center line takes over the pieces
+/// crossing between the carriageways from there, so each piece goes to at most one stub. The
+/// carriageway pieces that met at `existing_i` are kept as connectors, so the stub still refers to
+/// the DC when nothing crosses it, like at a T-junction.
+fn stub_kind(
+    existing_i: IntersectionID,
+    crossing_edges: &mut Vec<Edge>,
+    carriageway_pieces: BTreeSet<OriginalEdgeID>,
+) -> EdgeKind {
+    let mut kind = EdgeKind::Motorized {
+        roads: BTreeSet::new(),
+        service_roads: BTreeSet::new(),
+        sidepaths: BTreeSet::new(),
+        sidepath_positions: BTreeMap::new(),
+        connectors: carriageway_pieces,
+    };
+    crossing_edges.retain(|edge| {
+        if edge.src != existing_i && edge.dst != existing_i {
+            return true;
+        }
+        if let Some(merged) = kind.merge(&edge.kind) {
+            kind = merged;
+        } else if let EdgeKind::Motorized { connectors, .. } = &mut kind {
+            // A footway or cycleway crossing
+            connectors.extend(edge.kind.all_original_edges());
+        }
+        false
+    });
+    kind
 }
 
-/// Returns the new intersections created
+/// Returns the new intersections created. There must be one kind per linestring.
 fn create_new_linked_edges(
     graph: &mut Graph,
     linestrings: Vec<LineString>,
     endpoints: Vec<Point>,
-) -> Vec<IntersectionID> {
+    kinds: Vec<EdgeKind>,
+) -> Result<Vec<IntersectionID>> {
     // Assumes linestrings all point in the correct way
     // Assumes endpoints comes from linestring_endpoints (TODO maybe just call it here)
-    assert_eq!(linestrings.len() + 1, endpoints.len());
+    if linestrings.len() + 1 != endpoints.len() {
+        bail!(
+            "{} split center lines don't match {} endpoints",
+            linestrings.len(),
+            endpoints.len()
+        );
+    }
 
     let mut new_intersections = Vec::new();
     for point in endpoints {
-        let id = graph.new_intersection_id();
-        graph.intersections.insert(
-            id,
-            Intersection {
-                id,
-                edges: vec![],
-                point,
-                provenance: IntersectionProvenance::Synthetic,
-            },
-        );
-        new_intersections.push(id);
+        new_intersections.push(graph.create_new_intersection(point));
     }
 
-    // TODO Wrong, we need to merge to preserve sidepath relationships and stuff. And not have one
-    // for the entire DC.
-    let kind = EdgeKind::Motorized {
-        roads: BTreeSet::new(),
-        service_roads: BTreeSet::new(),
-        sidepaths: BTreeSet::new(),
-        connectors: BTreeSet::new(),
-    };
-
-    for (idx, linestring) in linestrings.into_iter().enumerate() {
+    for (idx, (linestring, kind)) in linestrings.into_iter().zip(kinds).enumerate() {
         graph.create_new_edge(
             linestring,
             new_intersections[idx],
             new_intersections[idx + 1],
-            kind.clone(),
-        );
+            kind,
+        )?;
     }
 
-    new_intersections
+    Ok(new_intersections)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_helpers::{crossed_dc, edge_near, face_containing, two_blocks, TestMap};
+
+    #[test]
+    fn test_collapse_keeps_crossing_roads_separate() {
+        let mut b = crossed_dc().build();
+        let id = face_containing(&b, 50.0, 10.0);
+        b.do_cmd(Command::CollapseDualCarriageway(id)).unwrap();
+
+        let name = |e: &Edge| e.kind.get_road_name(&b.graph).cloned();
+        let mut stubs = 0;
+        for edge in b.graph.edges.values() {
+            // Every edge still refers to something in OSM
+            assert!(
+                !edge.kind.all_original_edges().is_empty(),
+                "{:?} is empty",
+                edge.id
+            );
+            let EdgeKind::Motorized { roads, .. } = &edge.kind else {
+                panic!("{:?} isn't a road", edge.id);
+            };
+            // Stubs where nothing crosses the DC only refer to the carriageways
+            if roads.is_empty() {
+                continue;
+            }
+            match name(edge).as_deref() {
+                Some("Main Street") => {}
+                Some("Side Street") => {
+                    if Euclidean.length(&edge.linestring) < 20.0 {
+                        stubs += 1;
+                    }
+                }
+                x => panic!("{:?} has name {x:?}", edge.id),
+            }
+        }
+        // Side Street is reconnected to the center line from both sides, and only one of those
+        // stubs takes over the piece between the carriageways
+        assert_eq!(stubs, 1);
+    }
+
+    #[test]
+    fn test_geometry_ignores_oneways_across_the_face() {
+        // Unnamed carriageways, with a oneway link between them
+        let oneway = &[("highway", "primary"), ("oneway", "yes")];
+        let b = TestMap::default()
+            .way(&[(-50.0, 10.0), (0.0, 10.0)], &[("highway", "primary")])
+            .way(&[(200.0, 10.0), (250.0, 10.0)], &[("highway", "primary")])
+            .way(
+                &[
+                    (0.0, 10.0),
+                    (20.0, 0.0),
+                    (100.0, 0.0),
+                    (180.0, 0.0),
+                    (200.0, 10.0),
+                ],
+                oneway,
+            )
+            .way(
+                &[
+                    (200.0, 10.0),
+                    (180.0, 20.0),
+                    (100.0, 20.0),
+                    (20.0, 20.0),
+                    (0.0, 10.0),
+                ],
+                oneway,
+            )
+            .way(&[(100.0, 0.0), (100.0, 20.0)], oneway)
+            .build();
+        let link = edge_near(&b, 100.0, 10.0);
+        let face = &b.faces[&face_containing(&b, 50.0, 10.0)];
+        assert!(face.boundary_edges.contains(&link));
+
+        let (signal, _, dc_edges) = detect_dc_edges(&b.graph, face).unwrap();
+        assert_eq!(signal, DcSignal::Geometry);
+        assert_eq!(dc_edges.len(), 2);
+        assert!(!dc_edges.contains(&link));
+    }
+
+    #[test]
+    fn test_collapse_rejects_other_faces() {
+        let mut b = two_blocks();
+        let edges_before: Vec<EdgeID> = b.graph.edges.keys().cloned().collect();
+        for id in b.faces.keys().cloned().collect::<Vec<_>>() {
+            let err = b.do_cmd(Command::CollapseDualCarriageway(id)).unwrap_err();
+            assert!(
+                err.to_string().contains("isn't a dual carriageway"),
+                "{err}"
+            );
+        }
+        assert_eq!(
+            b.graph.edges.keys().cloned().collect::<Vec<_>>(),
+            edges_before
+        );
+        assert_eq!(b.undo_count(), 0);
+    }
 
     #[test]
     fn test_classify_bearings() {
-        for (input, expected) in vec![
+        for (input, expected) in [
             (vec![90, 90, 91, 98, 265, 271], vec![0, 0, 0, 0, 1, 1]),
             (vec![90, 270], vec![0, 1]),
             (vec![85, 90, 90, 90, 270, 274], vec![0, 0, 0, 0, 1, 1]),
@@ -310,4 +1178,55 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_aspect_ratio() {
+        for (width, height, expected) in [(100.0, 10.0, 10.0), (10.0, 10.0, 1.0), (5.0, 20.0, 4.0)]
+        {
+            let polygon = geo::Rect::new((0.0, 0.0), (width, height)).to_polygon();
+            let got = aspect_ratio(&polygon);
+            assert!(
+                (got - expected).abs() < 1e-6,
+                "For a {width}x{height} rectangle, got {got}, want {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_original_carriageway_role() {
+        let tags = |pairs: Vec<(&str, &str)>| {
+            let mut tags = Tags::default();
+            for (k, v) in pairs {
+                tags.insert(k, v);
+            }
+            tags
+        };
+        let name = "High Street".to_string();
+        for (input, service_road, expected) in [
+            (
+                vec![("highway", "primary"), ("name", "High Street")],
+                false,
+                CarriagewayRole::Main,
+            ),
+            (
+                vec![("highway", "primary"), ("name", "Low Street")],
+                false,
+                CarriagewayRole::Other,
+            ),
+            (
+                vec![("highway", "service"), ("name", "High Street")],
+                true,
+                CarriagewayRole::Service,
+            ),
+            (vec![("highway", "busway")], false, CarriagewayRole::Bus),
+            (
+                vec![("highway", "service"), ("access", "no"), ("psv", "yes")],
+                true,
+                CarriagewayRole::Bus,
+            ),
+        ] {
+            let got = original_carriageway_role(&tags(input.clone()), service_road, Some(&name));
+            assert_eq!(got, expected, "For {input:?}");
+        }
+    }
 }
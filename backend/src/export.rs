@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use geo::{Euclidean, Length};
+use geojson::Feature;
+use osm_reader::{NodeID, WayID};
+use serde::Serialize;
+use utils::Tags;
+
+use crate::dual_carriageway::{main_road_name, original_carriageway_role, CarriagewayRole};
+use crate::geo_helpers::runs_along;
+use crate::graph::OriginalEdgeID;
+use crate::{EdgeID, EdgeKind, Graph, RoadBundler};
+
+/// Keys that OSM splits into `:forward` and `:backward` variants for two-way roads
+const DIRECTIONAL_KEYS: [&str; 4] = ["lanes", "turn:lanes", "maxspeed", "destination"];
+
+/// Which way traffic on an original road travels, relative to the linestring of an edge
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// All of the pieces of one OSM way bundled into an edge in one role
+#[derive(Serialize)]
+pub struct OsmWayPieces {
+    pub way: WayID,
+    /// Each piece of the way goes between two nodes
+    pub node_ranges: Vec<(NodeID, NodeID)>,
+    pub tags: Tags,
+}
+
+impl RoadBundler {
+    /// Like `edges_to_gj`, but instead of `OriginalEdgeID`s that only make sense in this session,
+    /// describe the OSM ways bundled into each edge, grouped by role
+    pub fn bundled_edges_to_gj(&self) -> Result<Vec<Feature>> {
+        let empty = BTreeSet::new();
+        let mut features = Vec::new();
+        for (id, edge) in &self.graph.edges {
+            let mut f = self.graph.mercator.to_wgs84_gj(&edge.linestring);
+            f.set_property("edge_id", id.0);
+            f.set_property("simple_kind", edge.kind.to_simple());
+            f.set_property("length", Euclidean.length(&edge.linestring).round());
+
+            let (roads, service_roads, sidepaths, connectors, nonmotorized) = match &edge.kind {
+                EdgeKind::Motorized {
+                    roads,
+                    service_roads,
+                    sidepaths,
+                    connectors,
+                    ..
+                } => (roads, service_roads, sidepaths, connectors, &empty),
+                EdgeKind::Nonmotorized(edges) => (&empty, &empty, &empty, &empty, edges),
+            };
+            for (role, original_edges) in [
+                ("roads", roads),
+                ("service_roads", service_roads),
+                ("sidepaths", sidepaths),
+                ("connectors", connectors),
+                ("nonmotorized", nonmotorized),
+            ] {
+                f.set_property(
+                    role,
+                    serde_json::to_value(group_by_way(&self.graph, original_edges))?,
+                );
+            }
+
+            f.set_property(
+                "cross_section",
+                serde_json::to_value(self.get_cross_section(*id)?)?,
+            );
+
+            if let EdgeKind::Motorized { sidepaths, .. } = &edge.kind {
+                // Where each sidepath way runs along the edge, as fractions of its length
+                let mut ranges: BTreeMap<WayID, (f64, f64)> = BTreeMap::new();
+                for s in sidepaths {
+                    let Some((start, end)) = self.sidepath_range(*id, *s) else {
+                        continue;
+                    };
+                    let range = ranges
+                        .entry(self.graph.original_edges[s].way)
+                        .or_insert((start, end));
+                    *range = (range.0.min(start), range.1.max(end));
+                }
+                f.set_property("sidepath_ranges", serde_json::to_value(ranges)?);
+            }
+
+            let directions = self.oneway_road_directions(*id);
+            for (property, dir) in [
+                ("forward_ways", Direction::Forward),
+                ("backward_ways", Direction::Backward),
+            ] {
+                let ways: BTreeSet<WayID> = directions
+                    .iter()
+                    .filter(|(_, d)| **d == dir)
+                    .map(|(e, _)| self.graph.original_edges[e].way)
+                    .collect();
+                if !ways.is_empty() {
+                    f.set_property(property, serde_json::to_value(ways)?);
+                }
+            }
+            if let Some(carriageways) = self.carriageways(*id) {
+                f.set_property("carriageways", serde_json::to_value(carriageways)?);
+            }
+
+            if let Some(tags) = self.directional_tags(*id) {
+                f.set_property("directional_tags", serde_json::to_value(&tags)?);
+            }
+
+            features.push(f);
+        }
+        Ok(features)
+    }
+}
+
+impl RoadBundler {
+    /// For every oneway road bundled into an edge, which way it travels along the edge's
+    /// linestring. Two-way roads are omitted.
+    pub fn oneway_road_directions(&self, e: EdgeID) -> BTreeMap<OriginalEdgeID, Direction> {
+        let edge = &self.graph.edges[&e];
+        let EdgeKind::Motorized { roads, .. } = &edge.kind else {
+            return BTreeMap::new();
+        };
+
+        let mut directions = BTreeMap::new();
+        for orig_id in roads {
+            let tags = &self.graph.original_edges[orig_id].tags;
+            let reversed = if tags.is("oneway", "yes") {
+                false
+            } else if tags.is("oneway", "-1") {
+                true
+            } else {
+                continue;
+            };
+            // The original geometry always follows the OSM way
+            let original = &self.original_graph.edges[&EdgeID(orig_id.0)].linestring;
+            let along = runs_along(&edge.linestring, original);
+            directions.insert(
+                *orig_id,
+                if along != reversed {
+                    Direction::Forward
+                } else {
+                    Direction::Backward
+                },
+            );
+        }
+        directions
+    }
+
+    /// When an edge bundles oneway roads going both ways, like a collapsed dual carriageway,
+    /// describe it as one two-way road, with per-direction tags taken from each side. Values that
+    /// differ along one side are left out.
+    pub fn directional_tags(&self, e: EdgeID) -> Option<Tags> {
+        let directions = self.oneway_road_directions(e);
+        let side = |dir| -> Vec<&Tags> {
+            directions
+                .iter()
+                .filter(|(_, d)| **d == dir)
+                .map(|(orig_id, _)| &self.graph.original_edges[orig_id].tags)
+                .collect()
+        };
+        let forward = side(Direction::Forward);
+        let backward = side(Direction::Backward);
+        if forward.is_empty() || backward.is_empty() {
+            return None;
+        }
+
+        let mut tags = Tags::default();
+        tags.insert("oneway", "no");
+        for key in DIRECTIONAL_KEYS {
+            for (suffix, side) in [("forward", &forward), ("backward", &backward)] {
+                if let Some(value) = common_value(side, key) {
+                    tags.insert(format!("{key}:{suffix}"), value);
+                }
+            }
+        }
+        if let (Some(Ok(fwd)), Some(Ok(back))) = (
+            tags.get("lanes:forward").map(|x| x.parse::<usize>()),
+            tags.get("lanes:backward").map(|x| x.parse::<usize>()),
+        ) {
+            tags.insert("lanes", (fwd + back).to_string());
+        }
+        Some(tags)
+    }
+}
+
+impl RoadBundler {
+    /// When an edge bundles several carriageways of one road, like a collapsed dual carriageway
+    /// with a bus-only road in the middle, groups the OSM ways by their role. The main road is
+    /// the name or ref shared by all the others.
+    pub fn carriageways(&self, e: EdgeID) -> Option<BTreeMap<CarriagewayRole, BTreeSet<WayID>>> {
+        let EdgeKind::Motorized {
+            roads,
+            service_roads,
+            ..
+        } = &self.graph.edges[&e].kind
+        else {
+            return None;
+        };
+        let name = main_road_name(&self.graph, &self.graph.edges[&e].kind);
+
+        let mut carriageways: BTreeMap<CarriagewayRole, BTreeSet<WayID>> = BTreeMap::new();
+        for (orig_id, service_road) in roads
+            .iter()
+            .map(|e| (e, false))
+            .chain(service_roads.iter().map(|e| (e, true)))
+        {
+            let orig = &self.graph.original_edges[orig_id];
+            carriageways
+                .entry(original_carriageway_role(&orig.tags, service_road, name))
+                .or_default()
+                .insert(orig.way);
+        }
+        (carriageways.len() > 1).then_some(carriageways)
+    }
+}
+
+/// The value of a tag, only if every road has the same one
+fn common_value<'a>(roads: &[&'a Tags], key: &str) -> Option<&'a String> {
+    let values: BTreeSet<_> = roads.iter().map(|tags| tags.get(key)).collect();
+    if values.len() != 1 {
+        return None;
+    }
+    values.into_iter().next().unwrap()
+}
+
+/// Groups original edges by their OSM way
+pub fn group_by_way(graph: &Graph, original_edges: &BTreeSet<OriginalEdgeID>) -> Vec<OsmWayPieces> {
+    let mut by_way: BTreeMap<WayID, OsmWayPieces> = BTreeMap::new();
+    for e in original_edges {
+        let orig = &graph.original_edges[e];
+        by_way
+            .entry(orig.way)
+            .or_insert_with(|| OsmWayPieces {
+                way: orig.way,
+                node_ranges: Vec::new(),
+                tags: orig.tags.clone(),
+            })
+            .node_ranges
+            .push((orig.node1, orig.node2));
+    }
+    by_way.into_values().collect()
+}
@@ -1,23 +1,51 @@
+use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
 
+use anyhow::Result;
 use geo::buffer::{BufferStyle, LineJoin};
 use geo::{
-    BooleanOps, Buffer, Centroid, Contains, Distance, Euclidean, InterpolatableLine, LineString,
-    MultiLineString, Polygon,
+    unary_union, Area, BooleanOps, Buffer, Centroid, Contains, Distance, Euclidean,
+    InterpolatableLine, Intersects, LineString, MultiLineString, MultiPolygon, Point, Polygon,
 };
 use geojson::Feature;
-use rstar::{primitives::GeomWithData, RTree};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{RTree, RTreeObject};
+use serde::{Deserialize, Serialize};
 use utils::{aabb, split_polygon, Debugger};
 
 use crate::geo_helpers::SliceNearEndpoints;
-use crate::{
-    Areas, EdgeID, EdgeKind, Graph, Intersection, IntersectionID, IntersectionProvenance,
-    RoadBundler,
-};
+use crate::graph::ChangedEdge;
+use crate::{Areas, EdgeID, EdgeKind, Graph, IntersectionID, RoadBundler};
 
+/// Derived from the face's boundary edges, so that faces not touched by an edit keep the same ID,
+/// no matter what order faces are calculated in.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
-pub struct FaceID(pub usize);
+pub struct FaceID(pub u64);
+
+// JavaScript numbers can't hold every 64-bit integer, so this is a string in JSON
+impl Serialize for FaceID {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for FaceID {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Edits saved before IDs were 64 bits have numbers
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Saved {
+            Number(u64),
+            String(String),
+        }
+        match Saved::deserialize(deserializer)? {
+            Saved::Number(x) => Ok(FaceID(x)),
+            Saved::String(x) => x.parse().map(FaceID).map_err(serde::de::Error::custom),
+        }
+    }
+}
 
+#[derive(Clone)]
 pub struct Face {
     pub polygon: Polygon,
     pub kind: FaceKind,
@@ -28,7 +56,7 @@ pub struct Face {
     pub internal_edges: Vec<EdgeID>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum FaceKind {
     /// Should be not be simplified. There are buildings or real land uses inside. This also
     /// includes parking aisles.
@@ -41,7 +69,32 @@ pub enum FaceKind {
     OtherArea,
 }
 
+/// Spatial indices of the current edges and faces. `update_faces` keeps them in sync, so they
+/// don't have to be rebuilt after every command.
+pub struct SpatialIndex {
+    edges: RTree<GeomWithData<LineString, EdgeID>>,
+    faces: RTree<GeomWithData<Rectangle<Point>, FaceID>>,
+}
+
+impl SpatialIndex {
+    pub fn new(graph: &Graph, faces: &BTreeMap<FaceID, Face>) -> Self {
+        Self {
+            edges: edges_rtree(graph),
+            faces: faces_rtree(faces),
+        }
+    }
+}
+
 pub fn make_faces(graph: &Graph, areas: &Areas) -> BTreeMap<FaceID, Face> {
+    info!("Building rtree for {} edges", graph.edges.len());
+    make_all_faces(graph, areas, &edges_rtree(graph))
+}
+
+fn make_all_faces(
+    graph: &Graph,
+    areas: &Areas,
+    closest_edge: &RTree<GeomWithData<LineString, EdgeID>>,
+) -> BTreeMap<FaceID, Face> {
     info!("Splitting {} edges into faces", graph.edges.len());
     let polygons = split_polygon(
         &graph.boundary_polygon,
@@ -53,84 +106,265 @@ pub fn make_faces(graph: &Graph, areas: &Areas) -> BTreeMap<FaceID, Face> {
         ),
     );
 
-    info!("Building rtree for {} edges", graph.edges.len());
-    let closest_edge = RTree::bulk_load(
-        graph
-            .edges
-            .values()
-            .map(|e| GeomWithData::new(e.linestring.clone(), e.id))
-            .collect(),
-    );
-
     info!("Matching {} faces with edges", polygons.len());
     let mut faces = BTreeMap::new();
     for polygon in polygons {
-        let bbox = aabb(&polygon);
+        insert_face(
+            &mut faces,
+            None,
+            make_face(graph, areas, closest_edge, polygon),
+        );
+    }
+    info!("Done");
+    faces
+}
+
+/// Instead of calling `make_faces` for the whole area, only recalculate the faces touching the
+/// old or new geometry of every edge that's been created, removed, or modified. Other faces keep
+/// their ID.
+pub fn update_faces(
+    graph: &Graph,
+    areas: &Areas,
+    faces: &mut BTreeMap<FaceID, Face>,
+    index: &mut SpatialIndex,
+    changes: Vec<ChangedEdge>,
+) {
+    if changes.is_empty() {
+        return;
+    }
+
+    let mut changed_linestrings = Vec::new();
+    for change in changes {
+        if let Some(ls) = change.before {
+            index
+                .edges
+                .remove(&GeomWithData::new(ls.clone(), change.id));
+            changed_linestrings.push(ls);
+        }
+        if let Some(ls) = change.after {
+            index.edges.insert(GeomWithData::new(ls.clone(), change.id));
+            changed_linestrings.push(ls);
+        }
+    }
 
-        let mut boundary_edges = Vec::new();
-        let mut internal_edges = Vec::new();
-        for obj in closest_edge.locate_in_envelope_intersecting(&bbox) {
-            if linestring_along_polygon(obj.geom(), &polygon) {
-                boundary_edges.push(obj.data);
-            } else if polygon.contains(obj.geom()) {
-                internal_edges.push(obj.data);
+    let mut affected = BTreeSet::new();
+    for ls in &changed_linestrings {
+        for obj in index.faces.locate_in_envelope_intersecting(&ls.envelope()) {
+            if faces[&obj.data].polygon.intersects(ls) {
+                affected.insert(obj.data);
             }
         }
+    }
 
-        let (boundary_intersections, connecting_edges) = find_connections(graph, &boundary_edges);
-        let num_buildings = areas
-            .building_centroids
-            .locate_in_envelope_intersecting(&bbox)
-            .filter(|pt| polygon.contains(*pt))
-            .count();
-        let num_other_areas = areas
-            .other_centroids
+    // When most of the area changes, just start over
+    if affected.len() * 2 > faces.len() {
+        *faces = make_all_faces(graph, areas, &index.edges);
+        index.faces = faces_rtree(faces);
+        return;
+    }
+
+    let mut old_faces = Vec::new();
+    for id in affected {
+        old_faces.push(remove_face(faces, index, id));
+    }
+    let region = unary_union(old_faces.iter().map(|face| &face.polygon));
+
+    let mut old_area = old_faces
+        .iter()
+        .map(|face| face.polygon.unsigned_area())
+        .sum::<f64>();
+    let mut new_faces = Vec::new();
+    for polygon in region {
+        // Any faces surrounded by the changed faces have to be recalculated too, so just ignore
+        // holes
+        let polygon = Polygon::new(polygon.into_inner().0, Vec::new());
+        let bbox = aabb(&polygon);
+        let surrounded: Vec<FaceID> = index
+            .faces
             .locate_in_envelope_intersecting(&bbox)
-            .filter(|pt| polygon.contains(*pt))
-            .count();
-        let has_parking_aisle = boundary_edges
-            .iter()
-            .any(|e| graph.edges[e].kind.is_parking_aisle(graph));
-        let mut num_roads = 0;
-        let mut num_non_roads = 0;
-        for e in &boundary_edges {
-            match graph.edges[e].kind {
-                EdgeKind::Motorized { .. } => {
-                    num_roads += 1;
+            .map(|obj| obj.data)
+            .filter(|id| polygon.contains(&faces[id].polygon))
+            .collect();
+        for id in surrounded {
+            old_area += remove_face(faces, index, id).polygon.unsigned_area();
+        }
+
+        new_faces.extend(split_polygon(
+            &polygon,
+            index
+                .edges
+                .locate_in_envelope_intersecting(&bbox)
+                .map(|obj| obj.geom())
+                .chain(
+                    areas
+                        .other_polygons
+                        .locate_in_envelope_intersecting(&bbox)
+                        .map(|polygon| polygon.exterior()),
+                ),
+        ));
+    }
+
+    // Merging and splitting polygons could go wrong in edge cases. If the new faces don't cover
+    // the same area, start over.
+    let new_area = new_faces
+        .iter()
+        .map(|polygon| polygon.unsigned_area())
+        .sum::<f64>();
+    if (new_area - old_area).abs() > 0.001 * old_area {
+        warn!("Incrementally updating faces changed the area from {old_area} to {new_area}; recalculating all faces");
+        *faces = make_all_faces(graph, areas, &index.edges);
+        index.faces = faces_rtree(faces);
+        return;
+    }
+
+    for polygon in new_faces {
+        let face = make_face(graph, areas, &index.edges, polygon);
+        insert_face(faces, Some(&mut index.faces), face);
+    }
+}
+
+/// Faces almost never hash to the same ID. When they do, the face with the smaller
+/// `tie_break_key` keeps the ID, and the other moves to the next one, possibly displacing another
+/// face in turn. This way, which face gets which ID doesn't depend on the order they're inserted.
+fn insert_face(
+    faces: &mut BTreeMap<FaceID, Face>,
+    mut index: Option<&mut RTree<GeomWithData<Rectangle<Point>, FaceID>>>,
+    mut face: Face,
+) {
+    let mut id = face.stable_id();
+    loop {
+        match faces.entry(id) {
+            Entry::Vacant(slot) => {
+                if let Some(index) = index {
+                    index.insert(face_bbox(id, &face));
                 }
-                EdgeKind::Nonmotorized(_) => {
-                    num_non_roads += 1;
+                slot.insert(face);
+                return;
+            }
+            Entry::Occupied(mut slot) => {
+                if face.tie_break_key() < slot.get().tie_break_key() {
+                    if let Some(index) = index.as_deref_mut() {
+                        index.remove(&face_bbox(id, slot.get()));
+                        index.insert(face_bbox(id, &face));
+                    }
+                    face = std::mem::replace(slot.get_mut(), face);
                 }
+                id = FaceID(id.0.wrapping_add(1));
             }
         }
-        let kind = if num_buildings > 0 {
-            FaceKind::UrbanBlock
-        } else if num_other_areas > 0 {
-            FaceKind::OtherArea
-        } else if num_roads > 0 && num_non_roads > 0 {
-            FaceKind::SidepathArtifact
-        } else if has_parking_aisle {
-            // Order matters -- sidepaths next to a parking aisle count as SidepathArtifact
-            FaceKind::UrbanBlock
-        } else {
-            FaceKind::RoadArtifact
-        };
+    }
+}
 
-        let id = FaceID(faces.len());
-        faces.insert(
-            id,
-            Face {
-                polygon,
-                kind,
-                boundary_edges,
-                boundary_intersections,
-                connecting_edges,
-                internal_edges,
-            },
-        );
+fn remove_face(faces: &mut BTreeMap<FaceID, Face>, index: &mut SpatialIndex, id: FaceID) -> Face {
+    let face = faces.remove(&id).unwrap();
+    index.faces.remove(&face_bbox(id, &face));
+
+    // Faces displaced by a collision might belong in the freed ID now
+    let mut next = FaceID(id.0.wrapping_add(1));
+    let mut displaced = Vec::new();
+    while let Some(other) = faces.get(&next) {
+        if other.stable_id() != next {
+            displaced.push(next);
+        }
+        next = FaceID(next.0.wrapping_add(1));
+    }
+    for other in displaced {
+        let other_face = faces.remove(&other).unwrap();
+        index.faces.remove(&face_bbox(other, &other_face));
+        insert_face(faces, Some(&mut index.faces), other_face);
+    }
+
+    face
+}
+
+fn edges_rtree(graph: &Graph) -> RTree<GeomWithData<LineString, EdgeID>> {
+    RTree::bulk_load(
+        graph
+            .edges
+            .values()
+            .map(|e| GeomWithData::new(e.linestring.clone(), e.id))
+            .collect(),
+    )
+}
+
+fn faces_rtree(faces: &BTreeMap<FaceID, Face>) -> RTree<GeomWithData<Rectangle<Point>, FaceID>> {
+    RTree::bulk_load(
+        faces
+            .iter()
+            .map(|(id, face)| face_bbox(*id, face))
+            .collect(),
+    )
+}
+
+fn face_bbox(id: FaceID, face: &Face) -> GeomWithData<Rectangle<Point>, FaceID> {
+    GeomWithData::new(Rectangle::from_aabb(aabb(&face.polygon)), id)
+}
+
+fn make_face(
+    graph: &Graph,
+    areas: &Areas,
+    closest_edge: &RTree<GeomWithData<LineString, EdgeID>>,
+    polygon: Polygon,
+) -> Face {
+    let bbox = aabb(&polygon);
+
+    let mut boundary_edges = Vec::new();
+    let mut internal_edges = Vec::new();
+    for obj in closest_edge.locate_in_envelope_intersecting(&bbox) {
+        if linestring_along_polygon(obj.geom(), &polygon) {
+            boundary_edges.push(obj.data);
+        } else if polygon.contains(obj.geom()) {
+            internal_edges.push(obj.data);
+        }
+    }
+
+    let (boundary_intersections, connecting_edges) = find_connections(graph, &boundary_edges);
+    let num_buildings = areas
+        .building_centroids
+        .locate_in_envelope_intersecting(&bbox)
+        .filter(|pt| polygon.contains(*pt))
+        .count();
+    let num_other_areas = areas
+        .other_centroids
+        .locate_in_envelope_intersecting(&bbox)
+        .filter(|pt| polygon.contains(*pt))
+        .count();
+    let has_parking_aisle = boundary_edges
+        .iter()
+        .any(|e| graph.edges[e].kind.is_parking_aisle(graph));
+    let mut num_roads = 0;
+    let mut num_non_roads = 0;
+    for e in &boundary_edges {
+        match graph.edges[e].kind {
+            EdgeKind::Motorized { .. } => {
+                num_roads += 1;
+            }
+            EdgeKind::Nonmotorized(_) => {
+                num_non_roads += 1;
+            }
+        }
+    }
+    let kind = if num_buildings > 0 {
+        FaceKind::UrbanBlock
+    } else if num_other_areas > 0 {
+        FaceKind::OtherArea
+    } else if num_roads > 0 && num_non_roads > 0 {
+        FaceKind::SidepathArtifact
+    } else if has_parking_aisle {
+        // Order matters -- sidepaths next to a parking aisle count as SidepathArtifact
+        FaceKind::UrbanBlock
+    } else {
+        FaceKind::RoadArtifact
+    };
+
+    Face {
+        polygon,
+        kind,
+        boundary_edges,
+        boundary_intersections,
+        connecting_edges,
+        internal_edges,
     }
-    info!("Done");
-    faces
 }
 
 fn linestring_along_polygon(ls: &LineString, polygon: &Polygon) -> bool {
@@ -184,25 +418,25 @@ fn find_connections(
 }
 
 impl RoadBundler {
-    pub fn collapse_to_centroid(&mut self, id: FaceID) {
-        let face = &self.faces[&id];
+    pub fn collapse_to_centroid(&mut self, id: FaceID) -> Result<()> {
+        let Some(face) = self.faces.get(&id) else {
+            bail!("{id:?} doesn't exist");
+        };
+        let Some(centroid) = face.polygon.centroid() else {
+            bail!("{id:?} has no centroid");
+        };
 
-        // TODO How/where do we preserve associations? Edges are becoming a node
+        // The new intersection remembers the edges that become part of it
+        let mut absorbed_edges = BTreeSet::new();
         for e in &face.boundary_edges {
-            self.graph.remove_edge(*e);
+            absorbed_edges.extend(self.graph.remove_edge(*e)?.kind.all_original_edges());
         }
 
         // Create a new intersection at the centroid
-        let new_intersection = self.graph.new_intersection_id();
-        self.graph.intersections.insert(
-            new_intersection,
-            Intersection {
-                id: new_intersection,
-                edges: Vec::new(),
-                point: face.polygon.centroid().expect("no face centroid"),
-                provenance: IntersectionProvenance::Synthetic,
-            },
-        );
+        let new_intersection = self.graph.create_new_intersection(centroid);
+        let intersection = self.graph.intersection_mut(new_intersection).unwrap();
+        intersection.absorbed_edges = absorbed_edges;
+        intersection.footprint = MultiPolygon::new(vec![face.polygon.clone()]);
 
         for i in &face.boundary_intersections {
             // Remove this intersection, reconnecting the surviving edges instead to the new
@@ -212,13 +446,56 @@ impl RoadBundler {
             // two roads jutting off from the same node.
             let extend_geometry = true;
             self.graph
-                .replace_intersection(*i, new_intersection, extend_geometry);
+                .replace_intersection(*i, new_intersection, extend_geometry)?;
         }
+        Ok(())
     }
 }
 
 impl Face {
-    pub fn to_gj(&self, graph: &Graph, id: FaceID) -> Feature {
+    /// Hashes the sorted boundary edges, or the centroid if there are none
+    fn stable_id(&self) -> FaceID {
+        let (boundary_edges, centroid) = self.tie_break_key();
+
+        // Use FNV-1a instead of std's hasher, which isn't guaranteed to be the same across Rust
+        // versions, because the IDs are saved in edit files
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut add = |x: u64| {
+            for byte in x.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        if boundary_edges.is_empty() {
+            add(centroid.0 as u64);
+            add(centroid.1 as u64);
+        } else {
+            for e in boundary_edges {
+                add(e.0 as u64);
+            }
+        }
+        FaceID(hash)
+    }
+
+    /// Orders faces that hash to the same ID. Faces on both sides of a loop have the same boundary
+    /// edges, so the centroid in centimeters breaks ties.
+    fn tie_break_key(&self) -> (Vec<EdgeID>, (i64, i64)) {
+        let mut boundary_edges = self.boundary_edges.clone();
+        boundary_edges.sort();
+        let centroid = self
+            .polygon
+            .centroid()
+            .map(|pt| {
+                (
+                    (pt.x() * 100.0).round() as i64,
+                    (pt.y() * 100.0).round() as i64,
+                )
+            })
+            .unwrap_or((0, 0));
+        (boundary_edges, centroid)
+    }
+
+    pub fn to_gj(&self, graph: &Graph, areas: &Areas, id: FaceID) -> Feature {
         let mut debug_hover = Debugger::new(graph.mercator.clone());
         for e in &self.boundary_edges {
             debug_hover.line(&graph.edges[e].linestring, "boundary edge", "red", 5, 1.0);
@@ -237,13 +514,19 @@ impl Face {
         }
 
         let mut f = graph.mercator.to_wgs84_gj(&self.polygon);
-        f.set_property("face_id", id.0);
+        f.set_property("face_id", id.0.to_string());
         f.set_property("debug_hover", debug_hover.build());
         f.set_property("kind", format!("{:?}", self.kind));
-        match crate::dual_carriageway::DualCarriageway::maybe_new(graph, self) {
+        match crate::dual_carriageway::DualCarriageway::maybe_new(graph, self, Default::default()) {
             Ok(dc) => f.set_property("dual_carriageway", serde_json::to_value(&dc).unwrap()),
             Err(err) => f.set_property("dual_carriageway", err.to_string()),
         }
+        match crate::roundabout::Roundabout::maybe_new(graph, self, &areas.mini_roundabouts) {
+            Ok(roundabout) => {
+                f.set_property("roundabout", serde_json::to_value(&roundabout).unwrap())
+            }
+            Err(err) => f.set_property("roundabout", err.to_string()),
+        }
         match crate::sidepath::detect_sidepath(graph, self) {
             Ok(gj) => f.set_property("sidepath", serde_json::to_value(&gj).unwrap()),
             Err(err) => f.set_property("sidepath", err.to_string()),
@@ -328,6 +611,8 @@ impl Face {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_helpers::{edge_near, face_containing, TestMap};
+    use crate::Command;
     use geo::wkt;
 
     #[test]
@@ -336,4 +621,99 @@ mod tests {
         let ls = wkt!(LINESTRING(190.27018264451632 40.65292133361041,190.64442083873556 48.99255235061214,190.76417706118355 53.862896864869825,195.98105748674922 65.92756307056125));
         assert!(linestring_along_polygon(&ls, &polygon));
     }
+
+    /// A 3x3 grid of blocks, 100m apart
+    fn grid() -> RoadBundler {
+        let mut map = TestMap::default();
+        for i in 0..4 {
+            let c = 100.0 * i as f64;
+            map = map
+                .road(
+                    &[(0.0, c), (100.0, c), (200.0, c), (300.0, c)],
+                    "Horizontal",
+                )
+                .road(&[(c, 0.0), (c, 100.0), (c, 200.0), (c, 300.0)], "Vertical");
+        }
+        map.build()
+    }
+
+    fn describe(faces: &BTreeMap<FaceID, Face>) -> Vec<String> {
+        faces
+            .iter()
+            .map(|(id, face)| {
+                let mut boundary_edges = face.boundary_edges.clone();
+                boundary_edges.sort();
+                format!(
+                    "{id:?} {:?} {boundary_edges:?} {:.1}",
+                    face.kind,
+                    face.polygon.unsigned_area()
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_update_faces_matches_make_faces() {
+        let mut b = grid();
+        let num_faces = b.faces.len();
+
+        let cmds = [
+            // Merge two blocks
+            Command::RemoveEdge(edge_near(&b, 150.0, 100.0)),
+            Command::CollapseToCentroid(face_containing(&b, 250.0, 250.0)),
+            Command::CollapseEdge(edge_near(&b, 50.0, 300.0)),
+            Command::RemoveAllFootways,
+        ];
+        for cmd in cmds {
+            b.do_cmd(cmd).unwrap();
+            assert_eq!(
+                describe(&b.faces),
+                describe(&make_faces(&b.graph, &b.areas)),
+                "after {cmd:?}"
+            );
+        }
+        assert!(b.faces.len() < num_faces);
+
+        // The spatial index matches the final state too
+        let index = SpatialIndex::new(&b.graph, &b.faces);
+        assert_eq!(b.index.edges.size(), index.edges.size());
+        assert_eq!(b.index.faces.size(), index.faces.size());
+    }
+
+    #[test]
+    fn test_face_ids_are_stable() {
+        let mut b = grid();
+        let far_away = face_containing(&b, 250.0, 250.0);
+        b.do_cmd(Command::RemoveEdge(edge_near(&b, 50.0, 100.0)))
+            .unwrap();
+
+        // Faces not touched by the edit keep their ID, and recalculating everything gives the
+        // same IDs as updating incrementally
+        assert_eq!(face_containing(&b, 250.0, 250.0), far_away);
+        assert_eq!(
+            b.faces.keys().collect::<Vec<_>>(),
+            make_faces(&b.graph, &b.areas).keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_face_id_collisions() {
+        // Two roads between the same two points. The faces inside and outside have the same
+        // boundary edges, so they hash to the same ID.
+        let b = TestMap::default()
+            .road(&[(0.0, 0.0), (100.0, 0.0)], "Straight")
+            .road(&[(0.0, 0.0), (50.0, 50.0), (100.0, 0.0)], "Bent")
+            .build();
+        assert_eq!(b.faces.len(), 2);
+        let inside = face_containing(&b, 50.0, 10.0);
+        let outside = face_containing(&b, 500.0, 500.0);
+        assert_eq!(b.faces[&inside].stable_id(), b.faces[&outside].stable_id());
+        assert_eq!(outside.0, inside.0.wrapping_add(1));
+
+        // The IDs don't depend on the order faces are inserted
+        let mut faces = BTreeMap::new();
+        insert_face(&mut faces, None, b.faces[&outside].clone());
+        insert_face(&mut faces, None, b.faces[&inside].clone());
+        assert_eq!(describe(&faces), describe(&b.faces));
+    }
 }
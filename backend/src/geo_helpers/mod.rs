@@ -1,12 +1,17 @@
 // TODO Consider upstreaming all of these
 
 mod average_lines;
+mod skeleton;
 mod slice_nearest_boundary;
 
 pub use average_lines::average_linestrings;
-pub use slice_nearest_boundary::SliceNearEndpoints;
+pub use skeleton::skeleton_linestrings;
+pub use slice_nearest_boundary::{SliceNearEndpoints, SliceNearestFrechetBoundary};
 
-use geo::{Coord, Line, LineString};
+use geo::{
+    Coord, Distance, Euclidean, Length, Line, LineLocatePoint, LineString, MinimumRotatedRect,
+    Point, Polygon,
+};
 
 /// The bearing from the linestring's start to end
 pub fn linestring_bearing(linestring: &LineString) -> f64 {
@@ -15,6 +20,90 @@ pub fn linestring_bearing(linestring: &LineString) -> f64 {
     euclidean_bearing(pt1, pt2)
 }
 
+// or anti-parallel
+pub fn roughly_parallel(b1: f64, b2: f64) -> bool {
+    let diff = ((b1 - b2 + 180.0).abs() % 360.0 - 180.0).abs();
+    let tolerance = 30.0;
+    diff < tolerance || (diff - 180.0).abs() < tolerance
+}
+
+/// The bearing along the longer side of the polygon's minimum rotated rectangle
+pub fn long_axis_bearing(polygon: &Polygon) -> Option<f64> {
+    let rect = polygon.minimum_rotated_rect()?;
+    let pts = &rect.exterior().0;
+    if pts.len() < 3 {
+        return None;
+    }
+    let side1 = Line::new(pts[0], pts[1]);
+    let side2 = Line::new(pts[1], pts[2]);
+    let longer = if Euclidean.length(&side1) >= Euclidean.length(&side2) {
+        side1
+    } else {
+        side2
+    };
+    Some(euclidean_bearing(longer.start, longer.end))
+}
+
+/// Does `ls` point the same way as `line`, judging by where its endpoints project?
+pub fn runs_along(line: &LineString, ls: &LineString) -> bool {
+    let (Some(first), Some(last)) = (ls.0.first(), ls.0.last()) else {
+        return true;
+    };
+    let start = line.line_locate_point(&Point::from(*first)).unwrap_or(0.0);
+    let end = line.line_locate_point(&Point::from(*last)).unwrap_or(0.0);
+    start <= end
+}
+
+/// How far `ls` is from `line` on average, positive to the right when looking along `line`. Only
+/// the points of `ls` beside `line` count, unless there are none.
+pub fn signed_offset(line: &LineString, ls: &LineString) -> f64 {
+    let mut beside = Vec::new();
+    let mut all = Vec::new();
+    for pt in &ls.0 {
+        let distance = signed_distance(line, *pt);
+        all.push(distance);
+        if let Some(fraction) = line.line_locate_point(&Point::from(*pt)) {
+            if fraction > 0.0 && fraction < 1.0 {
+                beside.push(distance);
+            }
+        }
+    }
+    let distances = if beside.is_empty() { all } else { beside };
+    if distances.is_empty() {
+        return 0.0;
+    }
+    distances.iter().sum::<f64>() / distances.len() as f64
+}
+
+/// Where a linestring's endpoints project onto a line, as sorted fractions along it
+pub fn fraction_range(line: &LineString, ls: &LineString) -> (f64, f64) {
+    let mut fractions: Vec<f64> = [ls.0.first(), ls.0.last()]
+        .into_iter()
+        .flatten()
+        .map(|pt| line.line_locate_point(&Point::from(*pt)).unwrap_or(0.0))
+        .collect();
+    fractions.sort_by(|a, b| a.total_cmp(b));
+    (
+        fractions.first().cloned().unwrap_or(0.0),
+        fractions.last().cloned().unwrap_or(0.0),
+    )
+}
+
+/// The distance from `pt` to `line`, positive to the right when looking along `line`
+fn signed_distance(line: &LineString, pt: Coord) -> f64 {
+    let mut closest: Option<(f64, f64)> = None;
+    for segment in line.lines() {
+        let distance = Euclidean.distance(&segment, &Point::from(pt));
+        if closest.map(|(d, _)| distance < d).unwrap_or(true) {
+            // World-space Y points down, so the right side has a positive cross product
+            let cross =
+                segment.dx() * (pt.y - segment.start.y) - segment.dy() * (pt.x - segment.start.x);
+            closest = Some((distance, if cross >= 0.0 { distance } else { -distance }));
+        }
+    }
+    closest.map(|(_, signed)| signed).unwrap_or(0.0)
+}
+
 fn angle_of_line(line: Line) -> f64 {
     (line.dy()).atan2(line.dx()).to_degrees()
 }
@@ -26,3 +115,47 @@ fn angle_of_line(line: Line) -> f64 {
 fn euclidean_bearing(origin: Coord, destination: Coord) -> f64 {
     (angle_of_line(Line::new(origin, destination)) + 450.0) % 360.0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roughly_parallel() {
+        assert!(roughly_parallel(359., 360.));
+        assert!(roughly_parallel(359., 0.));
+        assert!(roughly_parallel(354., 2.));
+        assert!(roughly_parallel(179., 359.));
+
+        assert!(!roughly_parallel(179., 271.));
+        // south vs west
+    }
+
+    #[test]
+    fn test_long_axis_bearing() {
+        let wide = geo::Rect::new((0.0, 0.0), (100.0, 10.0)).to_polygon();
+        assert!(roughly_parallel(long_axis_bearing(&wide).unwrap(), 90.0));
+        let tall = geo::Rect::new((0.0, 0.0), (10.0, 100.0)).to_polygon();
+        assert!(roughly_parallel(long_axis_bearing(&tall).unwrap(), 0.0));
+    }
+
+    #[test]
+    fn test_signed_offset() {
+        // World-space Y points down, so heading east, the right is +Y
+        let line = LineString::from(vec![(0.0, 0.0), (100.0, 0.0)]);
+        let right = LineString::from(vec![(10.0, 5.0), (90.0, 5.0)]);
+        let left = LineString::from(vec![(10.0, -5.0), (90.0, -5.0)]);
+        assert!((signed_offset(&line, &right) - 5.0).abs() < 1e-6);
+        assert!((signed_offset(&line, &left) + 5.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_fraction_range() {
+        let road = LineString::from(vec![(0.0, 0.0), (100.0, 0.0)]);
+        // A sidewalk along the second half of the block, mapped in the opposite direction
+        let sidewalk = LineString::from(vec![(90.0, 5.0), (50.0, 5.0)]);
+        let (start, end) = fraction_range(&road, &sidewalk);
+        assert!((start - 0.5).abs() < 1e-6);
+        assert!((end - 0.9).abs() < 1e-6);
+    }
+}
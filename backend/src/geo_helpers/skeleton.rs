@@ -0,0 +1,151 @@
+use anyhow::Result;
+use geo::{
+    Coord, Densify, Distance, Euclidean, LineLocatePoint, LineString, Point, Polygon,
+    TriangulateDelaunay,
+};
+
+/// Builds the center line between two roughly parallel linestrings from the chordal axis of the
+/// corridor between them, approximating its medial axis. The corridor is triangulated, and the
+/// midpoints of every triangle edge crossing from one side to the other are joined up. Unlike
+/// `average_linestrings`, this copes with curves and with sides of very different lengths. The
+/// result points the same way as `ls1`.
+pub fn skeleton_linestrings(ls1: &LineString, ls2: &LineString) -> Result<LineString> {
+    // Much smaller than the gap between carriageways, so the triangles form a ladder across the
+    // corridor
+    let max_segment_length = 2.0;
+
+    let side1 = Euclidean.densify(ls1, max_segment_length);
+    let mut side2 = Euclidean.densify(ls2, max_segment_length);
+    // Walk around the corridor along side1, then back along side2
+    if points_same_way(&side1, &side2) {
+        side2.0.reverse();
+    }
+    let mut ring = side1.0.clone();
+    ring.extend(side2.0.iter().cloned());
+    let corridor = Polygon::new(LineString::new(ring), Vec::new());
+
+    let triangles = corridor
+        .constrained_triangulation(Default::default())
+        .map_err(|err| anyhow!("couldn't triangulate the corridor: {err:?}"))?;
+
+    let on_side1 = |pt: Coord| {
+        let pt = Point::from(pt);
+        Euclidean.distance(&pt, &side1) <= Euclidean.distance(&pt, &side2)
+    };
+    // Each rung is ordered by how far along the corridor both of its ends are
+    let mut rungs: Vec<(f64, Coord)> = Vec::new();
+    for triangle in triangles {
+        let [a, b, c] = triangle.to_array();
+        for (pt1, pt2) in [(a, b), (b, c), (c, a)] {
+            let (pt1, pt2) = match (on_side1(pt1), on_side1(pt2)) {
+                (true, false) => (pt1, pt2),
+                (false, true) => (pt2, pt1),
+                _ => continue,
+            };
+            let fraction1 = side1.line_locate_point(&pt1.into()).unwrap_or(0.0);
+            // side2 runs backwards
+            let fraction2 = 1.0 - side2.line_locate_point(&pt2.into()).unwrap_or(0.0);
+            rungs.push(((fraction1 + fraction2) / 2.0, (pt1 + pt2) / 2.0));
+        }
+    }
+
+    // Rungs shared by two triangles show up twice
+    rungs.sort_by(|(f1, pt1), (f2, pt2)| {
+        f1.total_cmp(f2)
+            .then(pt1.x.total_cmp(&pt2.x))
+            .then(pt1.y.total_cmp(&pt2.y))
+    });
+    let mut pts: Vec<Coord> = rungs.into_iter().map(|(_, pt)| pt).collect();
+    pts.dedup();
+
+    if pts.len() < 2 {
+        bail!("corridor between the two sides is too small");
+    }
+    Ok(LineString::new(pts))
+}
+
+/// Does ls2 start closer to the start of ls1 than to its end?
+fn points_same_way(ls1: &LineString, ls2: &LineString) -> bool {
+    let start = |ls: &LineString| Point::from(ls.0[0]);
+    let end = |ls: &LineString| Point::from(ls.0[ls.0.len() - 1]);
+    Euclidean.distance(start(ls1), start(ls2)) < Euclidean.distance(end(ls1), start(ls2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arc(radius: f64, from_degrees: f64, to_degrees: f64) -> LineString {
+        let steps = 18;
+        (0..=steps)
+            .map(|i| {
+                let angle = (from_degrees + (to_degrees - from_degrees) * i as f64 / steps as f64)
+                    .to_radians();
+                (radius * angle.cos(), radius * angle.sin())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_skeleton_center_line() {
+        // The carriageways point opposite ways, and the center line should be equidistant from
+        // both, besides the end caps
+        for (name, side1, side2, tolerance) in [
+            (
+                "straight",
+                LineString::from(vec![(0.0, 0.0), (100.0, 0.0)]),
+                LineString::from(vec![(100.0, 10.0), (0.0, 10.0)]),
+                0.1,
+            ),
+            (
+                "tapered",
+                LineString::from(vec![(0.0, 0.0), (100.0, 0.0)]),
+                LineString::from(vec![(100.0, 20.0), (0.0, 6.0)]),
+                1.0,
+            ),
+            ("curved", arc(50.0, 0.0, 90.0), arc(62.0, 90.0, 0.0), 0.5),
+            (
+                "sharp curve",
+                arc(15.0, 0.0, 150.0),
+                arc(25.0, 150.0, 0.0),
+                0.5,
+            ),
+        ] {
+            let center = skeleton_linestrings(&side1, &side2).unwrap();
+            for pt in &center.0[1..center.0.len() - 1] {
+                let pt = Point::from(*pt);
+                let d1 = Euclidean.distance(&pt, &side1);
+                let d2 = Euclidean.distance(&pt, &side2);
+                assert!(
+                    (d1 - d2).abs() < tolerance,
+                    "For {name}, {pt:?} is {d1} from side1 and {d2} from side2"
+                );
+            }
+            assert!(
+                Euclidean.distance(Point::from(center.0[0]), Point::from(side1.0[0]))
+                    < Euclidean.distance(
+                        Point::from(center.0[center.0.len() - 1]),
+                        Point::from(side1.0[0])
+                    ),
+                "For {name}, the center line doesn't point the same way as side1"
+            );
+        }
+    }
+
+    #[test]
+    fn test_skeleton_center_line_uneven_sides() {
+        // One carriageway is much shorter. Where the sides overlap, the center line should be
+        // halfway between them.
+        let side1 = LineString::from(vec![(0.0, 0.0), (100.0, 0.0)]);
+        let side2 = LineString::from(vec![(70.0, 10.0), (30.0, 10.0)]);
+        let center = skeleton_linestrings(&side1, &side2).unwrap();
+        for pt in &center.0 {
+            if pt.x > 32.0 && pt.x < 68.0 {
+                assert!((pt.y - 5.0).abs() < 0.1, "{pt:?} isn't halfway");
+            }
+        }
+        assert!(Euclidean.distance(Point::from(center.0[0]), Point::new(15.0, 5.0)) < 2.0);
+        let last = center.0[center.0.len() - 1];
+        assert!(Euclidean.distance(Point::from(last), Point::new(85.0, 5.0)) < 2.0);
+    }
+}
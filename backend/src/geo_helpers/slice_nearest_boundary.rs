@@ -31,7 +31,6 @@ pub trait SliceNearestFrechetBoundary {
     /// All points in the output will be *topologically* within `self`, however the first and
     /// final points of the output may not appear explicitly in `self`, in which case they
     /// represent splitting the existing segments at the point nearest `closest_to`.
-    #[allow(unused)]
     fn slice_nearest_frechet_boundary(&self, closest_to: &LineString) -> (LineString, f64);
 }
 
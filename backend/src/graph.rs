@@ -1,8 +1,9 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-use geo::{LineString, Point, Polygon};
+use anyhow::Result;
+use geo::{BooleanOps, LineString, MultiPolygon, Point, Polygon};
 use osm_reader::{NodeID, WayID};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use utils::{Mercator, Tags};
 
 use crate::EdgeKind;
@@ -19,16 +20,45 @@ pub struct Graph {
 
     intersection_id_counter: usize,
     edge_id_counter: usize,
+    changes: Changes,
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+/// Everything modified in a `Graph` since `start_changes`, so a failed command can be rolled back
+/// and only faces near changed edges have to be recalculated
+#[derive(Clone, Default)]
+struct Changes {
+    /// Each edge before its first change, or `None` if it was created
+    edges: BTreeMap<EdgeID, Option<Edge>>,
+    intersections: BTreeMap<IntersectionID, Option<Intersection>>,
+    intersection_id_counter: usize,
+    edge_id_counter: usize,
+}
+
+/// An edge that was created, removed, or modified since `start_changes`. Changes to only the kind
+/// have the same geometry before and after.
+pub struct ChangedEdge {
+    pub id: EdgeID,
+    pub before: Option<LineString>,
+    pub after: Option<LineString>,
+}
+
+/// A copy of the parts of a `Graph` that commands modify
+#[derive(Clone)]
+pub struct GraphCheckpoint {
+    pub edges: BTreeMap<EdgeID, Edge>,
+    intersections: BTreeMap<IntersectionID, Intersection>,
+    intersection_id_counter: usize,
+    edge_id_counter: usize,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct EdgeID(pub usize);
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
 pub struct OriginalEdgeID(pub usize);
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct IntersectionID(pub usize);
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Edge {
     pub id: EdgeID,
     pub src: IntersectionID,
@@ -51,11 +81,17 @@ pub struct Intersection {
     pub id: IntersectionID,
     pub edges: Vec<EdgeID>,
     pub point: Point,
-    #[allow(unused)]
     pub provenance: IntersectionProvenance,
+    /// Original edges that were collapsed into this intersection, like the ring of a roundabout
+    pub absorbed_edges: BTreeSet<OriginalEdgeID>,
+    /// Other OSM nodes that were merged into this intersection
+    pub absorbed_nodes: BTreeSet<NodeID>,
+    /// The area of any faces collapsed into this intersection. Empty for most intersections.
+    pub footprint: MultiPolygon,
 }
 
 #[derive(Clone, Serialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum IntersectionProvenance {
     OSM(NodeID),
     Synthetic,
@@ -84,8 +120,8 @@ impl Graph {
         Self {
             edges: osm_graph
                 .edges
-                .into_iter()
-                .map(|(_, e)| {
+                .into_values()
+                .map(|e| {
                     (
                         e.id.into(),
                         Edge {
@@ -100,8 +136,8 @@ impl Graph {
                 .collect(),
             intersections: osm_graph
                 .intersections
-                .into_iter()
-                .map(|(_, i)| {
+                .into_values()
+                .map(|i| {
                     (
                         i.id.into(),
                         Intersection {
@@ -109,6 +145,9 @@ impl Graph {
                             edges: i.edges.into_iter().map(|e| e.into()).collect(),
                             point: i.point,
                             provenance: IntersectionProvenance::OSM(i.osm_node),
+                            absorbed_edges: BTreeSet::new(),
+                            absorbed_nodes: BTreeSet::new(),
+                            footprint: MultiPolygon::new(Vec::new()),
                         },
                     )
                 })
@@ -119,54 +158,179 @@ impl Graph {
 
             intersection_id_counter,
             edge_id_counter,
+            changes: Changes::default(),
+        }
+    }
+
+    pub fn checkpoint(&self) -> GraphCheckpoint {
+        GraphCheckpoint {
+            edges: self.edges.clone(),
+            intersections: self.intersections.clone(),
+            intersection_id_counter: self.intersection_id_counter,
+            edge_id_counter: self.edge_id_counter,
         }
     }
 
-    pub fn new_intersection_id(&mut self) -> IntersectionID {
+    pub fn restore(&mut self, checkpoint: GraphCheckpoint) {
+        self.edges = checkpoint.edges;
+        self.intersections = checkpoint.intersections;
+        self.intersection_id_counter = checkpoint.intersection_id_counter;
+        self.edge_id_counter = checkpoint.edge_id_counter;
+        self.changes = Changes::default();
+    }
+
+    /// Starts recording changes. Everything modifying edges and intersections has to go through
+    /// methods here, so the changes are recorded.
+    pub fn start_changes(&mut self) {
+        self.changes = Changes {
+            edges: BTreeMap::new(),
+            intersections: BTreeMap::new(),
+            intersection_id_counter: self.intersection_id_counter,
+            edge_id_counter: self.edge_id_counter,
+        };
+    }
+
+    /// Undoes everything since `start_changes`
+    pub fn rollback_changes(&mut self) {
+        let changes = std::mem::take(&mut self.changes);
+        for (id, before) in changes.edges {
+            match before {
+                Some(edge) => {
+                    self.edges.insert(id, edge);
+                }
+                None => {
+                    self.edges.remove(&id);
+                }
+            }
+        }
+        for (id, before) in changes.intersections {
+            match before {
+                Some(intersection) => {
+                    self.intersections.insert(id, intersection);
+                }
+                None => {
+                    self.intersections.remove(&id);
+                }
+            }
+        }
+        self.intersection_id_counter = changes.intersection_id_counter;
+        self.edge_id_counter = changes.edge_id_counter;
+    }
+
+    /// Stops recording changes, returning every edge that's different since `start_changes`
+    pub fn finish_changes(&mut self) -> Vec<ChangedEdge> {
+        let changes = std::mem::take(&mut self.changes);
+        changes
+            .edges
+            .into_iter()
+            .filter_map(|(id, before)| {
+                let after = self.edges.get(&id);
+                if before.as_ref() == after {
+                    return None;
+                }
+                Some(ChangedEdge {
+                    id,
+                    before: before.map(|edge| edge.linestring),
+                    after: after.map(|edge| edge.linestring.clone()),
+                })
+            })
+            .collect()
+    }
+
+    fn record_edge(&mut self, e: EdgeID) {
+        if !self.changes.edges.contains_key(&e) {
+            self.changes.edges.insert(e, self.edges.get(&e).cloned());
+        }
+    }
+
+    fn record_intersection(&mut self, i: IntersectionID) {
+        if !self.changes.intersections.contains_key(&i) {
+            self.changes
+                .intersections
+                .insert(i, self.intersections.get(&i).cloned());
+        }
+    }
+
+    /// Modifies an edge in place
+    pub fn edge_mut(&mut self, e: EdgeID) -> Option<&mut Edge> {
+        self.record_edge(e);
+        self.edges.get_mut(&e)
+    }
+
+    /// Modifies an intersection in place
+    pub fn intersection_mut(&mut self, i: IntersectionID) -> Option<&mut Intersection> {
+        self.record_intersection(i);
+        self.intersections.get_mut(&i)
+    }
+
+    fn new_intersection_id(&mut self) -> IntersectionID {
         self.intersection_id_counter += 1;
         IntersectionID(self.intersection_id_counter)
     }
 
-    pub fn new_edge_id(&mut self) -> EdgeID {
+    fn new_edge_id(&mut self) -> EdgeID {
         self.edge_id_counter += 1;
         EdgeID(self.edge_id_counter)
     }
 
-    pub fn remove_edge(&mut self, e: EdgeID) -> Edge {
-        let edge = self
-            .edges
-            .remove(&e)
-            .expect("can't remove edge that doesn't exist");
+    pub fn remove_edge(&mut self, e: EdgeID) -> Result<Edge> {
+        self.record_edge(e);
+        let Some(edge) = self.edges.remove(&e) else {
+            bail!("can't remove {e:?}, which doesn't exist");
+        };
         for i in [edge.src, edge.dst] {
-            let intersection = self.intersections.get_mut(&i).unwrap();
+            let Some(intersection) = self.intersection_mut(i) else {
+                bail!("{e:?} is connected to {i:?}, which doesn't exist");
+            };
             intersection.edges.retain(|x| *x != e);
             // If edge.src == edge.dst, this is idempotent
         }
-        edge
+        Ok(edge)
     }
 
-    pub fn remove_empty_intersection(&mut self, i: IntersectionID) {
-        let intersection = self
-            .intersections
-            .remove(&i)
-            .expect("can't remove intersection that doesn't exist");
+    pub fn remove_empty_intersection(&mut self, i: IntersectionID) -> Result<Intersection> {
+        let Some(intersection) = self.intersections.get(&i) else {
+            bail!("can't remove {i:?}, which doesn't exist");
+        };
         if !intersection.edges.is_empty() {
-            panic!("intersection wasn't empty, but removed");
+            bail!("can't remove {i:?}, which still has edges");
         }
+        self.record_intersection(i);
+        Ok(self.intersections.remove(&i).unwrap())
     }
 
     pub fn remove_all_empty_intersections(&mut self) {
-        let remove_intersections: Vec<_> = self
+        let empty: Vec<IntersectionID> = self
             .intersections
-            .iter()
-            .filter(|(_, i)| i.edges.is_empty())
-            .map(|(id, _)| *id)
+            .values()
+            .filter(|i| i.edges.is_empty())
+            .map(|i| i.id)
             .collect();
-        for i in remove_intersections {
-            self.remove_empty_intersection(i);
+        for i in empty {
+            self.record_intersection(i);
+            self.intersections.remove(&i);
         }
     }
 
+    /// Creates an intersection not connected to anything yet
+    pub fn create_new_intersection(&mut self, point: Point) -> IntersectionID {
+        let id = self.new_intersection_id();
+        self.record_intersection(id);
+        self.intersections.insert(
+            id,
+            Intersection {
+                id,
+                edges: Vec::new(),
+                point,
+                provenance: IntersectionProvenance::Synthetic,
+                absorbed_edges: BTreeSet::new(),
+                absorbed_nodes: BTreeSet::new(),
+                footprint: MultiPolygon::new(Vec::new()),
+            },
+        );
+        id
+    }
+
     /// Trusts the linestring to go from `src` to `dst`
     pub fn create_new_edge(
         &mut self,
@@ -174,8 +338,14 @@ impl Graph {
         src: IntersectionID,
         dst: IntersectionID,
         kind: EdgeKind,
-    ) -> EdgeID {
+    ) -> Result<EdgeID> {
+        for i in [src, dst] {
+            if !self.intersections.contains_key(&i) {
+                bail!("can't create an edge to {i:?}, which doesn't exist");
+            }
+        }
         let id = self.new_edge_id();
+        self.record_edge(id);
         self.edges.insert(
             id,
             Edge {
@@ -186,54 +356,88 @@ impl Graph {
                 kind,
             },
         );
-        self.intersections.get_mut(&src).unwrap().edges.push(id);
-        self.intersections.get_mut(&dst).unwrap().edges.push(id);
-        id
+        self.intersection_mut(src).unwrap().edges.push(id);
+        self.intersection_mut(dst).unwrap().edges.push(id);
+        Ok(id)
     }
 
-    /// Extends the edge geometry in a way that probably overlaps
+    /// Extends the edge geometry in a way that probably overlaps. The new intersection absorbs
+    /// everything about the old one.
     pub fn replace_intersection(
         &mut self,
         remove_i: IntersectionID,
         new_intersection: IntersectionID,
         extend_geometry: bool,
-    ) {
-        let intersection = self
-            .intersections
-            .remove(&remove_i)
-            .expect("can't remove intersection that doesn't exist");
-        for surviving_edge in intersection.edges {
-            let edge = self.edges.get_mut(&surviving_edge).unwrap();
+    ) -> Result<()> {
+        let Some(new_point) = self.intersections.get(&new_intersection).map(|i| i.point) else {
+            bail!("can't replace {remove_i:?} with {new_intersection:?}, which doesn't exist");
+        };
+        self.record_intersection(remove_i);
+        let Some(mut intersection) = self.intersections.remove(&remove_i) else {
+            bail!("can't remove {remove_i:?}, which doesn't exist");
+        };
+        let surviving_edges = std::mem::take(&mut intersection.edges);
+        self.intersection_mut(new_intersection)
+            .unwrap()
+            .absorb(intersection);
+        for surviving_edge in surviving_edges {
+            let Some(edge) = self.edge_mut(surviving_edge) else {
+                bail!("{remove_i:?} is connected to {surviving_edge:?}, which doesn't exist");
+            };
             let mut updated = false;
             if edge.src == remove_i {
                 edge.src = new_intersection;
                 // TODO Update IntersectionProvenance?
                 if extend_geometry {
-                    edge.linestring
-                        .0
-                        .insert(0, self.intersections[&new_intersection].point.into());
+                    edge.linestring.0.insert(0, new_point.into());
                 }
                 updated = true;
             }
             if edge.dst == remove_i {
                 edge.dst = new_intersection;
                 if extend_geometry {
-                    edge.linestring
-                        .0
-                        .push(self.intersections[&new_intersection].point.into());
+                    edge.linestring.0.push(new_point.into());
                 }
                 updated = true;
             }
 
-            if updated {
-                self.intersections
-                    .get_mut(&new_intersection)
-                    .unwrap()
-                    .edges
-                    .push(surviving_edge);
-            } else {
-                panic!("replace_intersection saw inconsistent state about an edge connected to an intersection");
+            if !updated {
+                bail!("replace_intersection saw inconsistent state about {surviving_edge:?} connected to {remove_i:?}");
             }
+            self.intersection_mut(new_intersection)
+                .unwrap()
+                .edges
+                .push(surviving_edge);
+        }
+        Ok(())
+    }
+}
+
+impl Intersection {
+    /// The OSM node this intersection came from, if any, and every node merged into it
+    pub fn osm_nodes(&self) -> impl Iterator<Item = NodeID> + '_ {
+        let node = match self.provenance {
+            IntersectionProvenance::OSM(node) => Some(node),
+            IntersectionProvenance::Synthetic => None,
+        };
+        node.into_iter().chain(self.absorbed_nodes.iter().cloned())
+    }
+
+    /// Remembers everything about another intersection that's been collapsed into this one
+    pub fn absorb(&mut self, other: Intersection) {
+        if let IntersectionProvenance::OSM(node) = other.provenance {
+            self.absorbed_nodes.insert(node);
+        }
+        self.absorbed_nodes.extend(other.absorbed_nodes);
+        self.absorbed_edges.extend(other.absorbed_edges);
+        self.add_footprint(other.footprint);
+    }
+
+    pub fn add_footprint(&mut self, footprint: MultiPolygon) {
+        if self.footprint.0.is_empty() {
+            self.footprint = footprint;
+        } else if !footprint.0.is_empty() {
+            self.footprint = self.footprint.union(&footprint);
         }
     }
 }
@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::faces::SpatialIndex;
+use crate::graph::GraphCheckpoint;
+use crate::{Command, Face, FaceID, RoadBundler};
+
+/// After this many transactions, save a snapshot. Undo only has to replay the transactions since
+/// the last snapshot, but every snapshot holds a copy of the edges, intersections, and faces.
+const SNAPSHOT_INTERVAL: usize = 10;
+/// Keep at most this many snapshots besides the original one. Past that, every other snapshot is
+/// dropped, so the gaps between them grow further back in the history.
+const MAX_SNAPSHOTS: usize = 10;
+
+/// Bump this whenever the format of saved edits changes, and teach `load_transactions` to read
+/// the old one. Version 2 has 64-bit face IDs, saved as strings.
+const SAVED_EDITS_VERSION: usize = 2;
+
+/// How `commands` (or `OsmCommand`s) are saved to a file
+#[derive(Serialize, Deserialize)]
+struct SavedEdits<C> {
+    version: usize,
+    transactions: Vec<Vec<C>>,
+}
+
+/// The derived state after some number of transactions. The rest of the graph never changes.
+pub struct Snapshot {
+    pub graph: GraphCheckpoint,
+    pub faces: BTreeMap<FaceID, Face>,
+}
+
+impl RoadBundler {
+    /// Groups all commands pushed until the matching `finish_transaction` into one undoable
+    /// unit. Transactions can be nested; only the outermost one counts.
+    pub fn start_transaction(&mut self) {
+        if self.transaction_depth == 0 {
+            self.commands.push(Vec::new());
+        }
+        self.transaction_depth += 1;
+    }
+
+    pub fn finish_transaction(&mut self) {
+        self.transaction_depth -= 1;
+        if self.transaction_depth > 0 {
+            return;
+        }
+        // Transactions that didn't change anything don't count as a new edit, so they keep
+        // what could be redone
+        if self.commands.last().is_some_and(|t| t.is_empty()) {
+            self.commands.pop();
+        } else {
+            self.redo_stack.clear();
+            self.maybe_take_snapshot();
+        }
+    }
+
+    /// Applies a command and records it in the current transaction. If the command fails,
+    /// nothing changes.
+    pub fn push_cmd(&mut self, cmd: Command) -> Result<()> {
+        self.apply_cmd(cmd)?;
+        self.record_cmd(cmd);
+        Ok(())
+    }
+
+    /// Records a command in the current transaction, without applying it
+    pub fn record_cmd(&mut self, cmd: Command) {
+        assert!(
+            self.transaction_depth > 0,
+            "record_cmd called outside of a transaction"
+        );
+        self.commands.last_mut().unwrap().push(cmd);
+    }
+
+    /// Applies one command as its own transaction
+    pub fn do_cmd(&mut self, cmd: Command) -> Result<()> {
+        self.start_transaction();
+        let result = self.push_cmd(cmd);
+        self.finish_transaction();
+        result
+    }
+
+    /// Call after a transaction is added to `commands`
+    pub fn maybe_take_snapshot(&mut self) {
+        let num_transactions = self.commands.len();
+        if num_transactions.is_multiple_of(SNAPSHOT_INTERVAL) {
+            self.snapshots.insert(
+                num_transactions,
+                Snapshot {
+                    graph: self.graph.checkpoint(),
+                    faces: self.faces.clone(),
+                },
+            );
+            self.thin_snapshots();
+        }
+    }
+
+    /// Keep the original and latest snapshots, but drop every other one in between once there are
+    /// too many
+    fn thin_snapshots(&mut self) {
+        if self.snapshots.len() <= MAX_SNAPSHOTS + 1 {
+            return;
+        }
+        let keys: Vec<usize> = self.snapshots.keys().cloned().collect();
+        for (idx, n) in keys.iter().enumerate() {
+            if idx % 2 == 1 && idx != keys.len() - 1 {
+                self.snapshots.remove(n);
+            }
+        }
+    }
+
+    /// Recalculates `graph` and `faces` after `commands` has been changed, by applying every
+    /// transaction since the latest snapshot that's still valid.
+    ///
+    /// Snapshots only stay valid while the transactions before them are unchanged. Callers that
+    /// replace earlier transactions must `forget_snapshots` first.
+    pub fn restore_from_snapshot(&mut self) -> Result<()> {
+        let num_transactions = self.commands.len();
+        self.snapshots.retain(|n, _| *n <= num_transactions);
+
+        let (start, snapshot) = self
+            .snapshots
+            .last_key_value()
+            .expect("the snapshot for 0 transactions is missing");
+        self.graph.restore(snapshot.graph.clone());
+        self.faces = snapshot.faces.clone();
+        self.index = SpatialIndex::new(&self.graph, &self.faces);
+
+        let replay: Vec<Command> = self.commands[*start..].iter().flatten().cloned().collect();
+        for cmd in replay {
+            self.apply_cmd(cmd)?;
+        }
+        Ok(())
+    }
+
+    /// Resets `graph` and `faces` to the original state, without changing `commands`
+    pub fn restore_original(&mut self) {
+        let snapshot = &self.snapshots[&0];
+        self.graph.restore(snapshot.graph.clone());
+        self.faces = snapshot.faces.clone();
+        self.index = SpatialIndex::new(&self.graph, &self.faces);
+    }
+
+    /// Keep only the snapshot for the original state
+    pub fn forget_snapshots(&mut self) {
+        self.snapshots.retain(|n, _| *n == 0);
+    }
+}
+
+pub fn save_transactions<C: Serialize + Clone>(transactions: &[Vec<C>]) -> Result<String> {
+    Ok(serde_json::to_string(&SavedEdits {
+        version: SAVED_EDITS_VERSION,
+        transactions: transactions.to_vec(),
+    })?)
+}
+
+/// Reads edits from `save_transactions`, or any older format
+pub fn load_transactions<C: DeserializeOwned>(input: &str) -> Result<Vec<Vec<C>>> {
+    let value: serde_json::Value = serde_json::from_str(input)?;
+    let serde_json::Value::Array(list) = value else {
+        let saved: SavedEdits<C> = serde_json::from_value(value)?;
+        if saved.version > SAVED_EDITS_VERSION {
+            bail!(
+                "These edits were saved in format version {}, but only version {} and older is supported",
+                saved.version,
+                SAVED_EDITS_VERSION
+            );
+        }
+        return Ok(saved.transactions);
+    };
+
+    // Before there was a version, edits were saved as a bare list of transactions. Before
+    // transactions existed, they were a flat list of commands, each undone separately.
+    if list.iter().all(|x| x.is_array()) {
+        Ok(serde_json::from_value(serde_json::Value::Array(list))?)
+    } else {
+        let commands: Vec<C> = serde_json::from_value(serde_json::Value::Array(list))?;
+        Ok(commands.into_iter().map(|cmd| vec![cmd]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::EdgeID;
+    use crate::test_helpers::two_blocks;
+
+    fn describe(transactions: &[Vec<Command>]) -> String {
+        format!("{transactions:?}")
+    }
+
+    #[test]
+    fn test_load_old_formats() {
+        let expected = vec![
+            vec![Command::RemoveEdge(EdgeID(5)), Command::MergeAllSidepaths],
+            vec![Command::RemoveAllFootways],
+        ];
+        let saved = save_transactions(&expected).unwrap();
+        assert!(saved.starts_with("{\"version\":2,"));
+        assert_eq!(
+            describe(&load_transactions(&saved).unwrap()),
+            describe(&expected)
+        );
+
+        // Before versions
+        let bare = r#"[[{"RemoveEdge":5},"MergeAllSidepaths"],["RemoveAllFootways"]]"#;
+        assert_eq!(
+            describe(&load_transactions(bare).unwrap()),
+            describe(&expected)
+        );
+
+        // Before transactions, every command was its own
+        let flat = r#"[{"RemoveEdge":5},"RemoveAllFootways"]"#;
+        assert_eq!(
+            describe(&load_transactions(flat).unwrap()),
+            describe(&[
+                vec![Command::RemoveEdge(EdgeID(5))],
+                vec![Command::RemoveAllFootways]
+            ])
+        );
+
+        // Saved before there was a choice of center line, and before face IDs were strings
+        let dc = r#"[[{"CollapseDualCarriageway":123}]]"#;
+        assert_eq!(
+            describe(&load_transactions(dc).unwrap()),
+            describe(&[vec![Command::CollapseDualCarriageway(FaceID(123))]])
+        );
+
+        assert!(load_transactions::<Command>("[]").unwrap().is_empty());
+        assert!(load_transactions::<Command>(r#"{"version":3,"transactions":[]}"#).is_err());
+    }
+
+    #[test]
+    fn test_import_replays_edits() {
+        let mut original = two_blocks();
+        let num_faces = original.faces.len();
+        original.do_cmd(Command::RemoveEdge(EdgeID(5))).unwrap();
+        original.do_cmd(Command::RemoveAllFootways).unwrap();
+        // Road D separated two faces
+        assert_eq!(original.faces.len(), num_faces - 1);
+        let saved = save_transactions(&original.commands).unwrap();
+
+        let mut imported = two_blocks();
+        let num_cmds = imported
+            .import_commands(load_transactions(&saved).unwrap())
+            .unwrap();
+        assert_eq!(num_cmds, 2);
+        assert_eq!(imported.undo_count(), 2);
+        assert_eq!(
+            imported.graph.edges.keys().collect::<Vec<_>>(),
+            original.graph.edges.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            imported.faces.keys().collect::<Vec<_>>(),
+            original.faces.keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_failed_import_keeps_edits() {
+        let mut b = two_blocks();
+        let num_faces = b.faces.len();
+        b.do_cmd(Command::RemoveEdge(EdgeID(5))).unwrap();
+        b.do_cmd(Command::RemoveAllFootways).unwrap();
+        b.undo().unwrap();
+        let edges_before: Vec<EdgeID> = b.graph.edges.keys().cloned().collect();
+
+        // The first command is fine, but the second refers to an edge that doesn't exist
+        let err = b
+            .import_commands(
+                load_transactions(
+                    r#"{"version":1,"transactions":[["RemoveAllFootways",{"RemoveEdge":99}]]}"#,
+                )
+                .unwrap(),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("Command #2"), "{err}");
+
+        assert_eq!(
+            describe(&b.commands),
+            describe(&[vec![Command::RemoveEdge(EdgeID(5))]])
+        );
+        assert_eq!(
+            b.graph.edges.keys().cloned().collect::<Vec<_>>(),
+            edges_before
+        );
+        assert_eq!(b.faces.len(), num_faces - 1);
+        assert_eq!(b.redo_count(), 1);
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut b = two_blocks();
+        let original_edges: Vec<EdgeID> = b.graph.edges.keys().cloned().collect();
+        let num_faces = b.faces.len();
+
+        // Enough transactions to need a few snapshots
+        b.do_cmd(Command::RemoveEdge(EdgeID(5))).unwrap();
+        for _ in 0..25 {
+            b.do_cmd(Command::RemoveAllFootways).unwrap();
+        }
+        let edited_edges: Vec<EdgeID> = b.graph.edges.keys().cloned().collect();
+        assert_eq!(b.faces.len(), num_faces - 1);
+
+        for _ in 0..25 {
+            b.undo().unwrap();
+        }
+        assert_eq!(b.undo_count(), 1);
+        assert_eq!(b.redo_count(), 25);
+        assert_eq!(
+            b.graph.edges.keys().cloned().collect::<Vec<_>>(),
+            edited_edges
+        );
+
+        b.undo().unwrap();
+        assert_eq!(b.undo_count(), 0);
+        assert_eq!(
+            b.graph.edges.keys().cloned().collect::<Vec<_>>(),
+            original_edges
+        );
+        assert_eq!(b.faces.len(), num_faces);
+
+        b.redo().unwrap();
+        assert_eq!(
+            b.graph.edges.keys().cloned().collect::<Vec<_>>(),
+            edited_edges
+        );
+        assert_eq!(b.faces.len(), num_faces - 1);
+
+        // Bulk edits that don't change anything, or edits that fail, keep what could be redone
+        b.undo().unwrap();
+        let redo_count = b.redo_count();
+        assert_eq!(b.fix_all_dog_legs(), 0);
+        assert!(b.do_cmd(Command::RemoveEdge(EdgeID(99))).is_err());
+        assert_eq!(b.redo_count(), redo_count);
+
+        // A new edit forgets what could be redone
+        b.do_cmd(Command::RemoveAllFootways).unwrap();
+        assert_eq!(b.redo_count(), 0);
+    }
+
+    #[test]
+    fn test_snapshots_are_thinned() {
+        let mut b = two_blocks();
+        b.do_cmd(Command::RemoveEdge(EdgeID(5))).unwrap();
+        for _ in 0..200 {
+            b.do_cmd(Command::RemoveAllFootways).unwrap();
+            assert!(b.snapshots.len() <= MAX_SNAPSHOTS + 1);
+        }
+        assert!(b.snapshots.contains_key(&0));
+        assert!(b.snapshots.contains_key(&200));
+
+        // Undoing everything still works
+        let num_faces = b.faces.len();
+        while b.undo_count() > 0 {
+            b.undo().unwrap();
+        }
+        assert_eq!(b.faces.len(), num_faces + 1);
+    }
+}
@@ -1,11 +1,11 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use serde::Serialize;
 use utils::Tags;
 
 use crate::{graph::OriginalEdgeID, Graph};
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, PartialEq, Serialize)]
 pub enum EdgeKind {
     Motorized {
         /// The main driveable roads, possibly in different directions for a dual carriageway.
@@ -18,15 +18,44 @@ pub enum EdgeKind {
         /// Footways and cycleways that are parallel to the main driveable road. Might match to
         /// multiple edges.
         sidepaths: BTreeSet<OriginalEdgeID>,
+        /// Where each sidepath is relative to one of the roads, when known
+        sidepath_positions: BTreeMap<OriginalEdgeID, SidepathPosition>,
         /// Footway and cycleway crossings and related pieces that aren't parallel to the main
-        /// driveable road
-        // TODO and maybe pieces of DCs too?
+        /// driveable road. For the stubs reconnecting side roads to a collapsed dual carriageway,
+        /// the carriageway pieces they crossed.
         connectors: BTreeSet<OriginalEdgeID>,
     },
     /// Footways and cycleways that're off-road / not parallel to a driveable road
     Nonmotorized(BTreeSet<OriginalEdgeID>),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+        }
+    }
+}
+
+/// Where a sidepath is beside a road. This is relative to an original road edge, not the bundled
+/// edge, so it stays valid when edges are merged or reversed.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SidepathPosition {
+    /// The closest piece of road
+    pub road: OriginalEdgeID,
+    /// Looking along the road's OSM way
+    pub side: Side,
+    /// The average distance from the road, in meters
+    pub offset: f64,
+}
+
 impl EdgeKind {
     pub fn initially_classify(e: utils::osm2graph::EdgeID, tags: &Tags) -> Self {
         let id = BTreeSet::from([OriginalEdgeID(e.0)]);
@@ -53,6 +82,7 @@ impl EdgeKind {
                 roads: BTreeSet::new(),
                 service_roads: id,
                 sidepaths: BTreeSet::new(),
+                sidepath_positions: BTreeMap::new(),
                 connectors: BTreeSet::new(),
             };
         }
@@ -61,6 +91,7 @@ impl EdgeKind {
             roads: id,
             service_roads: BTreeSet::new(),
             sidepaths: BTreeSet::new(),
+            sidepath_positions: BTreeMap::new(),
             connectors: BTreeSet::new(),
         }
     }
@@ -73,18 +104,25 @@ impl EdgeKind {
                     roads: roads1,
                     service_roads: service_roads1,
                     sidepaths: sidepaths1,
+                    sidepath_positions: sidepath_positions1,
                     connectors: connectors1,
                 },
                 Self::Motorized {
                     roads: roads2,
                     service_roads: service_roads2,
                     sidepaths: sidepaths2,
+                    sidepath_positions: sidepath_positions2,
                     connectors: connectors2,
                 },
             ) => Some(Self::Motorized {
                 roads: roads1.union(roads2).cloned().collect(),
                 service_roads: service_roads1.union(service_roads2).cloned().collect(),
                 sidepaths: sidepaths1.union(sidepaths2).cloned().collect(),
+                sidepath_positions: sidepath_positions1
+                    .iter()
+                    .chain(sidepath_positions2)
+                    .map(|(e, position)| (*e, position.clone()))
+                    .collect(),
                 connectors: connectors1.union(connectors2).cloned().collect(),
             }),
             (Self::Nonmotorized(edges1), Self::Nonmotorized(edges2)) => {
@@ -94,6 +132,26 @@ impl EdgeKind {
         }
     }
 
+    /// Every original edge bundled here, in any role
+    pub fn all_original_edges(&self) -> Vec<OriginalEdgeID> {
+        match self {
+            Self::Motorized {
+                roads,
+                service_roads,
+                sidepaths,
+                connectors,
+                ..
+            } => roads
+                .iter()
+                .chain(service_roads)
+                .chain(sidepaths)
+                .chain(connectors)
+                .cloned()
+                .collect(),
+            Self::Nonmotorized(edges) => edges.iter().cloned().collect(),
+        }
+    }
+
     pub fn to_simple(&self) -> &'static str {
         match self {
             Self::Motorized {
@@ -129,9 +187,12 @@ impl EdgeKind {
 
     pub fn is_parking_aisle(&self, graph: &Graph) -> bool {
         match self {
-            EdgeKind::Motorized { service_roads, .. } => service_roads
-                .iter()
-                .all(|e| graph.original_edges[e].tags.is("service", "parking_aisle")),
+            EdgeKind::Motorized { service_roads, .. } => {
+                !service_roads.is_empty()
+                    && service_roads
+                        .iter()
+                        .all(|e| graph.original_edges[e].tags.is("service", "parking_aisle"))
+            }
             _ => false,
         }
     }
@@ -149,11 +210,16 @@ impl EdgeKind {
 
     /// Only if it's the same for all constituents
     pub fn get_road_name<'a>(&self, graph: &'a Graph) -> Option<&'a String> {
+        self.get_road_tag(graph, "name")
+    }
+
+    /// Only if it's the same for all constituents
+    pub fn get_road_tag<'a>(&self, graph: &'a Graph, key: &str) -> Option<&'a String> {
         match self {
             EdgeKind::Motorized { roads, .. } => {
                 let names: BTreeSet<_> = roads
                     .iter()
-                    .map(|e| graph.original_edges[e].tags.get("name"))
+                    .map(|e| graph.original_edges[e].tags.get(key))
                     .collect();
                 if names.len() != 1 {
                     return None;
@@ -5,30 +5,48 @@ extern crate anyhow;
 #[macro_use]
 extern crate log;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Once;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use geo::{Euclidean, Length};
-use geojson::GeoJson;
+use geojson::{Feature, GeoJson};
+use serde::{Deserialize, Serialize};
 use utils::Tags;
 use wasm_bindgen::prelude::*;
 
 use crate::areas::Areas;
-use crate::faces::{make_faces, Face, FaceID, FaceKind};
+pub use crate::cross_section::{Component, ComponentKind, CrossSection};
+pub use crate::diagnostics::{Diagnostics, FaceDiagnostic};
+pub use crate::dual_carriageway::CenterLineMethod;
+use crate::faces::{make_faces, update_faces, Face, FaceID, FaceKind, SpatialIndex};
 use crate::graph::{EdgeID, Graph, Intersection, IntersectionID, IntersectionProvenance};
+use crate::history::{load_transactions, save_transactions, Snapshot};
 use crate::kinds::EdgeKind;
+pub use crate::osm_commands::OsmCommand;
+pub use crate::recipe::{Recipe, Step};
+pub use crate::sidepath::NearbySidepaths;
 
 mod areas;
 mod clean;
+mod cross_section;
+mod diagnostics;
 mod dog_leg;
 mod dual_carriageway;
+mod export;
 mod faces;
 mod geo_helpers;
 mod graph;
+mod history;
 mod kinds;
+mod osm_commands;
+mod recipe;
+mod roundabout;
 mod sidepath;
+mod sidepath_tags;
 mod split_line;
+#[cfg(test)]
+mod test_helpers;
 mod width;
 
 static START: Once = Once::new();
@@ -37,11 +55,17 @@ static START: Once = Once::new();
 pub struct RoadBundler {
     original_graph: Graph,
     areas: Areas,
-    commands: Vec<Command>,
+    /// Each inner list is undone and redone as one unit
+    commands: Vec<Vec<Command>>,
+    redo_stack: Vec<Vec<Command>>,
+    transaction_depth: usize,
+    /// Keyed by the number of transactions applied. There's always one for 0.
+    snapshots: BTreeMap<usize, Snapshot>,
 
     // Derived
     graph: Graph,
     faces: BTreeMap<FaceID, Face>,
+    index: SpatialIndex,
 }
 
 #[wasm_bindgen]
@@ -54,51 +78,33 @@ impl RoadBundler {
             console_log::init_with_level(log::Level::Info).unwrap();
         });
 
-        let mut areas = areas::ReadOsmAreas::default();
-        let mut osm_graph =
-            utils::osm2graph::Graph::new(input_bytes, keep_edge, &mut areas).map_err(err_to_js)?;
-        osm_graph.compact_ids();
-        let graph = Graph::new(osm_graph);
-
-        let areas = areas.finalize(&graph.mercator);
-
-        let faces = make_faces(&graph, &areas);
-        Ok(Self {
-            original_graph: graph.clone(),
-            areas,
-            commands: Vec::new(),
-
-            graph,
-            faces,
-        })
+        Self::from_osm(input_bytes).map_err(err_to_js)
     }
 
     #[wasm_bindgen(js_name = getEdges)]
     pub fn get_edges(&self) -> Result<String, JsValue> {
-        let mut features = Vec::new();
-        for (id, edge) in &self.graph.edges {
-            let mut f = self.graph.mercator.to_wgs84_gj(&edge.linestring);
-            f.set_property("edge_id", id.0);
-            f.set_property("kind", serde_json::to_value(&edge.kind).map_err(err_to_js)?);
-            f.set_property("simple_kind", edge.kind.to_simple());
-            f.set_property("length", Euclidean.length(&edge.linestring).round());
-            f.set_property(
-                "bearing",
-                geo_helpers::linestring_bearing(&edge.linestring).round(),
-            );
-            features.push(f);
-        }
+        let features = self.edges_to_gj().map_err(err_to_js)?;
         serde_json::to_string(&GeoJson::from(features)).map_err(err_to_js)
     }
 
+    /// Like `getEdges`, but describing the OSM ways, nodes, and tags bundled into each edge
+    #[wasm_bindgen(js_name = getBundledEdges)]
+    pub fn get_bundled_edges(&self) -> Result<String, JsValue> {
+        let features = self.bundled_edges_to_gj().map_err(err_to_js)?;
+        serde_json::to_string(&GeoJson::from(features)).map_err(err_to_js)
+    }
+
+    /// A JOSM .osm file adding `is_sidepath` tags to the ways bundled as sidepaths. The input
+    /// must be the same one this was created from.
+    #[wasm_bindgen(js_name = getSidepathJosmOsm)]
+    pub fn get_sidepath_josm_osm(&self, input_bytes: &[u8]) -> Result<String, JsValue> {
+        self.sidepath_tags_to_josm_osm(input_bytes)
+            .map_err(err_to_js)
+    }
+
     #[wasm_bindgen(js_name = getIntersections)]
     pub fn get_intersections(&self) -> Result<String, JsValue> {
-        let mut features = Vec::new();
-        for (id, i) in &self.graph.intersections {
-            let mut f = self.graph.mercator.to_wgs84_gj(&i.point);
-            f.set_property("intersection_id", id.0);
-            features.push(f);
-        }
+        let features = self.intersections_to_gj().map_err(err_to_js)?;
         serde_json::to_string(&GeoJson::from(features)).map_err(err_to_js)
     }
 
@@ -117,7 +123,7 @@ impl RoadBundler {
             f.set_property("tags", serde_json::to_value(&orig.tags).unwrap());
             features.push(f);
         }
-        for (_, i) in &self.original_graph.intersections {
+        for i in self.original_graph.intersections.values() {
             features.push(self.graph.mercator.to_wgs84_gj(&i.point));
         }
         serde_json::to_string(&GeoJson::from(features)).map_err(err_to_js)
@@ -127,11 +133,35 @@ impl RoadBundler {
     pub fn get_faces(&self) -> Result<String, JsValue> {
         let mut features = Vec::new();
         for (id, face) in &self.faces {
-            features.push(face.to_gj(&self.graph, *id));
+            features.push(face.to_gj(&self.graph, &self.areas, *id));
         }
         serde_json::to_string(&GeoJson::from(features)).map_err(err_to_js)
     }
 
+    /// Explains why each face isn't a dual carriageway, roundabout, or sidepath
+    #[wasm_bindgen(js_name = getDiagnostics)]
+    pub fn get_diagnostics(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.diagnostics()).map_err(err_to_js)
+    }
+
+    /// Describes everything bundled into an edge, from left to right
+    #[wasm_bindgen(js_name = getCrossSection)]
+    pub fn get_cross_section_wasm(&self, id: usize) -> Result<String, JsValue> {
+        let cross_section = self.get_cross_section(EdgeID(id)).map_err(err_to_js)?;
+        serde_json::to_string(&cross_section).map_err(err_to_js)
+    }
+
+    /// The OSM ways of sidepaths running alongside an edge, some distance in meters from its start
+    #[wasm_bindgen(js_name = getSidepathsAt)]
+    pub fn get_sidepaths_at_wasm(&self, id: usize, distance: f64) -> Result<String, JsValue> {
+        let ways: BTreeSet<_> = self
+            .sidepaths_at(EdgeID(id), distance)
+            .into_iter()
+            .map(|e| self.graph.original_edges[&e].way)
+            .collect();
+        serde_json::to_string(&ways).map_err(err_to_js)
+    }
+
     #[wasm_bindgen(js_name = getBuildings)]
     pub fn get_buildings(&self) -> Result<String, JsValue> {
         let mut features = Vec::new();
@@ -152,94 +182,161 @@ impl RoadBundler {
     }
 
     #[wasm_bindgen(js_name = undo)]
-    pub fn undo(&mut self) {
-        self.commands.pop();
-        self.graph = self.original_graph.clone();
-        self.faces = make_faces(&self.graph, &self.areas);
+    pub fn undo(&mut self) -> Result<(), JsValue> {
+        if let Some(transaction) = self.commands.pop() {
+            self.redo_stack.push(transaction);
+        }
+        self.restore_from_snapshot().map_err(err_to_js)
+    }
 
-        for cmd in self.commands.clone() {
-            self.apply_cmd(cmd);
+    #[wasm_bindgen(js_name = redo)]
+    pub fn redo(&mut self) -> Result<(), JsValue> {
+        let Some(transaction) = self.redo_stack.pop() else {
+            return Ok(());
+        };
+        for cmd in &transaction {
+            if let Err(err) = self.apply_cmd(*cmd) {
+                // Undo the earlier commands in this transaction
+                self.redo_stack.push(transaction);
+                self.restore_from_snapshot().map_err(err_to_js)?;
+                return Err(err_to_js(err));
+            }
         }
+        self.commands.push(transaction);
+        self.maybe_take_snapshot();
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = undoCount)]
+    pub fn undo_count(&self) -> usize {
+        self.commands.len()
+    }
+
+    #[wasm_bindgen(js_name = redoCount)]
+    pub fn redo_count(&self) -> usize {
+        self.redo_stack.len()
+    }
+
+    #[wasm_bindgen(js_name = exportCommands)]
+    pub fn export_commands(&self) -> Result<String, JsValue> {
+        save_transactions(&self.commands).map_err(err_to_js)
+    }
+
+    /// Replaces the current edits with ones previously exported. Returns the number of commands
+    /// applied. If any command fails, the current edits are kept.
+    #[wasm_bindgen(js_name = importCommands)]
+    pub fn import_commands_wasm(&mut self, input: String) -> Result<usize, JsValue> {
+        let commands: Vec<Vec<Command>> = load_transactions(&input).map_err(err_to_js)?;
+        self.import_commands(commands).map_err(err_to_js)
+    }
+
+    /// Like `exportCommands`, but referencing OSM IDs, so the edits can be applied to a newer
+    /// extract of the same area. Returns the saved `edits` and a description of any `skipped`
+    /// that can't be expressed this way.
+    #[wasm_bindgen(js_name = exportOsmCommands)]
+    pub fn export_osm_commands_wasm(&mut self) -> Result<String, JsValue> {
+        let report = self.export_osm_commands().map_err(err_to_js)?;
+        let edits = save_transactions(&report.transactions).map_err(err_to_js)?;
+        serde_json::to_string(&serde_json::json!({
+            "edits": edits,
+            "skipped": report.skipped,
+        }))
+        .map_err(err_to_js)
+    }
+
+    /// Replaces the current edits with ones from `exportOsmCommands`. Returns a report with the
+    /// number of commands applied and a description of any that no longer resolve.
+    #[wasm_bindgen(js_name = importOsmCommands)]
+    pub fn import_osm_commands_wasm(&mut self, input: String) -> Result<String, JsValue> {
+        let osm_commands: Vec<Vec<OsmCommand>> = load_transactions(&input).map_err(err_to_js)?;
+        let report = self.import_osm_commands(osm_commands);
+        serde_json::to_string(&report).map_err(err_to_js)
     }
 
     #[wasm_bindgen(js_name = collapseToCentroid)]
-    pub fn collapse_to_centroid_wasm(&mut self, id: usize) {
+    pub fn collapse_to_centroid_wasm(&mut self, id: u64) -> Result<(), JsValue> {
         let cmd = Command::CollapseToCentroid(FaceID(id));
-        self.commands.push(cmd);
-        self.apply_cmd(cmd);
+        self.do_cmd(cmd).map_err(err_to_js)
     }
 
+    /// `center_line` is `average` or `skeleton`
     #[wasm_bindgen(js_name = collapseDualCarriageway)]
-    pub fn collapse_dual_carriageway_wasm(&mut self, id: usize) {
-        let cmd = Command::CollapseDualCarriageway(FaceID(id));
-        self.commands.push(cmd);
-        self.apply_cmd(cmd);
+    pub fn collapse_dual_carriageway_wasm(
+        &mut self,
+        id: u64,
+        center_line: String,
+    ) -> Result<(), JsValue> {
+        let method = parse_center_line_method(&center_line).map_err(err_to_js)?;
+        let cmd = Command::collapse_dual_carriageway(FaceID(id), method);
+        self.do_cmd(cmd).map_err(err_to_js)
     }
 
-    /// Returns the number of new commands applied
+    /// Returns the number of new commands applied. `center_line` is `average` or `skeleton`.
     #[wasm_bindgen(js_name = fixAllDualCarriageways)]
-    pub fn fix_all_dual_carriageways(&mut self) -> usize {
-        let mut cmds_applied = 0;
-
-        loop {
-            if let Some((id, _)) = self.faces.iter().find(|(_, face)| {
-                crate::dual_carriageway::DualCarriageway::maybe_new(&self.graph, face).is_ok()
-            }) {
-                let cmd = Command::CollapseDualCarriageway(*id);
-                self.commands.push(cmd);
-                self.apply_cmd(cmd);
-                cmds_applied += 1;
-            } else {
-                break;
-            }
-        }
+    pub fn fix_all_dual_carriageways_wasm(
+        &mut self,
+        center_line: String,
+    ) -> Result<usize, JsValue> {
+        let method = parse_center_line_method(&center_line).map_err(err_to_js)?;
+        Ok(self.fix_all_dual_carriageways(method))
+    }
 
-        cmds_applied
+    /// Returns the number of new commands applied
+    #[wasm_bindgen(js_name = fixAllRoundabouts)]
+    pub fn fix_all_roundabouts_wasm(&mut self) -> usize {
+        self.fix_all_roundabouts()
     }
 
     #[wasm_bindgen(js_name = mergeAllSidepaths)]
-    pub fn merge_all_sidepaths_wasm(&mut self) -> usize {
+    pub fn merge_all_sidepaths_wasm(&mut self) -> Result<usize, JsValue> {
         let cmd = Command::MergeAllSidepaths;
-        self.commands.push(cmd);
-        self.apply_cmd(cmd);
-        1
+        self.do_cmd(cmd).map_err(err_to_js)?;
+        Ok(1)
+    }
+
+    #[wasm_bindgen(js_name = mergeNearbySidepaths)]
+    pub fn merge_nearby_sidepaths_wasm(&mut self) -> Result<usize, JsValue> {
+        let cmd = Command::MergeNearbySidepaths(NearbySidepaths::default());
+        self.do_cmd(cmd).map_err(err_to_js)?;
+        Ok(1)
     }
 
     #[wasm_bindgen(js_name = removeAllFootways)]
-    pub fn remove_all_footways_wasm(&mut self) -> usize {
+    pub fn remove_all_footways_wasm(&mut self) -> Result<usize, JsValue> {
         let cmd = Command::RemoveAllFootways;
-        self.commands.push(cmd);
-        self.apply_cmd(cmd);
-        1
+        self.do_cmd(cmd).map_err(err_to_js)?;
+        Ok(1)
     }
 
     #[wasm_bindgen(js_name = removeEdge)]
-    pub fn remove_edge_wasm(&mut self, id: usize) -> usize {
+    pub fn remove_edge_wasm(&mut self, id: usize) -> Result<usize, JsValue> {
         let cmd = Command::RemoveEdge(EdgeID(id));
-        self.commands.push(cmd);
-        self.apply_cmd(cmd);
-        1
+        self.do_cmd(cmd).map_err(err_to_js)?;
+        Ok(1)
     }
 
     #[wasm_bindgen(js_name = removeAllServiceRoads)]
-    pub fn remove_all_service_roads_wasm(&mut self) -> usize {
+    pub fn remove_all_service_roads_wasm(&mut self) -> Result<usize, JsValue> {
         let cmd = Command::RemoveAllServiceRoads;
-        self.commands.push(cmd);
-        self.apply_cmd(cmd);
-        1
+        self.do_cmd(cmd).map_err(err_to_js)?;
+        Ok(1)
     }
 
     #[wasm_bindgen(js_name = collapseDegenerateIntersection)]
-    pub fn collapse_degenerate_intersection_wasm(&mut self, id: usize) {
+    pub fn collapse_degenerate_intersection_wasm(&mut self, id: usize) -> Result<(), JsValue> {
         let id = IntersectionID(id);
         // TODO Silently do nothing if invalid?
-        if self.graph.intersections[&id].edges.len() != 2 {
-            return;
+        if self
+            .graph
+            .intersections
+            .get(&id)
+            .is_some_and(|i| i.edges.len() != 2)
+        {
+            return Ok(());
         }
 
         let cmd = Command::CollapseDegenerateIntersection(id);
-        self.commands.push(cmd);
-        self.apply_cmd(cmd);
+        self.do_cmd(cmd).map_err(err_to_js)
     }
 
     #[wasm_bindgen(js_name = collapseAllDegenerateIntersections)]
@@ -254,70 +351,259 @@ impl RoadBundler {
 
         // TODO Cheating perf-wise here and not using apply_cmd, because we only need to
         // recalculate faces once
+        self.graph.start_changes();
+        self.start_transaction();
+        let num_recorded = self.commands.last().unwrap().len();
+        let mut failed = false;
         for id in &to_merge {
-            self.commands
-                .push(Command::CollapseDegenerateIntersection(*id));
-            self.collapse_degenerate_intersection(*id);
+            if let Err(err) = self.collapse_degenerate_intersection(*id) {
+                warn!(
+                    "Collapsing {id:?} failed, so collapsing each intersection separately: {err}"
+                );
+                failed = true;
+                break;
+            }
+            self.record_cmd(Command::CollapseDegenerateIntersection(*id));
         }
-        self.faces = make_faces(&self.graph, &self.areas);
 
-        to_merge.len()
+        let mut cmds_applied = to_merge.len();
+        if failed {
+            // Start over, skipping anything that fails
+            self.graph.rollback_changes();
+            self.commands.last_mut().unwrap().truncate(num_recorded);
+            cmds_applied = 0;
+            for id in to_merge {
+                match self.push_cmd(Command::CollapseDegenerateIntersection(id)) {
+                    Ok(()) => cmds_applied += 1,
+                    Err(err) => warn!("Skipping {id:?}: {err}"),
+                }
+            }
+        } else {
+            let changes = self.graph.finish_changes();
+            update_faces(
+                &self.graph,
+                &self.areas,
+                &mut self.faces,
+                &mut self.index,
+                changes,
+            );
+        }
+        self.finish_transaction();
+
+        cmds_applied
     }
 
     #[wasm_bindgen(js_name = collapseEdge)]
-    pub fn collapse_edge_wasm(&mut self, id: usize) {
+    pub fn collapse_edge_wasm(&mut self, id: usize) -> Result<(), JsValue> {
         let cmd = Command::CollapseEdge(EdgeID(id));
-        self.commands.push(cmd);
-        self.apply_cmd(cmd);
+        self.do_cmd(cmd).map_err(err_to_js)
     }
 
     /// Returns the number of new commands applied
     #[wasm_bindgen(js_name = fixAllDogLegs)]
     pub fn fix_all_dog_legs(&mut self) -> usize {
-        let mut cmds_applied = 0;
+        self.fix_all_dog_legs_with_max_length(dog_leg::DEFAULT_MAX_DOG_LEG_LENGTH)
+    }
 
-        loop {
-            if let Some(id) = self
-                .graph
-                .edges
-                .keys()
-                .find(|e| self.is_dog_leg(**e).is_some())
-            {
-                let cmd = Command::CollapseEdge(*id);
-                self.commands.push(cmd);
-                self.apply_cmd(cmd);
-                cmds_applied += 1;
-            } else {
-                break;
+    /// Returns the number of new commands applied
+    #[wasm_bindgen(js_name = applyRecipe)]
+    pub fn apply_recipe_wasm(&mut self, input: String) -> Result<usize, JsValue> {
+        let recipe: Recipe = serde_json::from_str(&input).map_err(err_to_js)?;
+        self.apply_recipe(&recipe).map_err(err_to_js)
+    }
+}
+
+impl RoadBundler {
+    /// Doesn't depend on wasm-bindgen, for use from native code
+    pub fn from_osm(input_bytes: &[u8]) -> Result<Self> {
+        let mut areas = areas::ReadOsmAreas::default();
+        let mut osm_graph = utils::osm2graph::Graph::new(input_bytes, keep_edge, &mut areas)?;
+        osm_graph.compact_ids();
+        Ok(Self::from_graph(osm_graph, areas))
+    }
+
+    fn from_graph(osm_graph: utils::osm2graph::Graph, areas: areas::ReadOsmAreas) -> Self {
+        let graph = Graph::new(osm_graph);
+
+        let areas = areas.finalize(&graph.mercator);
+
+        let faces = make_faces(&graph, &areas);
+        let index = SpatialIndex::new(&graph, &faces);
+        let snapshots = BTreeMap::from([(
+            0,
+            Snapshot {
+                graph: graph.checkpoint(),
+                faces: faces.clone(),
+            },
+        )]);
+        Self {
+            original_graph: graph.clone(),
+            areas,
+            commands: Vec::new(),
+            redo_stack: Vec::new(),
+            transaction_depth: 0,
+            snapshots,
+
+            graph,
+            faces,
+            index,
+        }
+    }
+
+    pub fn edges_to_gj(&self) -> Result<Vec<Feature>> {
+        let mut features = Vec::new();
+        for (id, edge) in &self.graph.edges {
+            let mut f = self.graph.mercator.to_wgs84_gj(&edge.linestring);
+            f.set_property("edge_id", id.0);
+            f.set_property("kind", serde_json::to_value(&edge.kind)?);
+            f.set_property("simple_kind", edge.kind.to_simple());
+            f.set_property("length", Euclidean.length(&edge.linestring).round());
+            f.set_property(
+                "bearing",
+                geo_helpers::linestring_bearing(&edge.linestring).round(),
+            );
+            features.push(f);
+        }
+        Ok(features)
+    }
+
+    pub fn intersections_to_gj(&self) -> Result<Vec<Feature>> {
+        let mut features = Vec::new();
+        for (id, i) in &self.graph.intersections {
+            let mut f = self.graph.mercator.to_wgs84_gj(&i.point);
+            f.set_property("intersection_id", id.0);
+            if let IntersectionProvenance::OSM(node) = i.provenance {
+                f.set_property("osm_node", node.0);
+            }
+            if let Some(roundabout) = self.intersection_roundabout(*id) {
+                f.set_property("roundabout", roundabout);
+            }
+            f.set_property(
+                "absorbed_ways",
+                serde_json::to_value(export::group_by_way(&self.graph, &i.absorbed_edges))?,
+            );
+            f.set_property(
+                "absorbed_nodes",
+                i.absorbed_nodes.iter().map(|n| n.0).collect::<Vec<_>>(),
+            );
+            if !i.footprint.0.is_empty() {
+                f.set_property(
+                    "footprint",
+                    serde_json::to_value(self.graph.mercator.to_wgs84_gj(&i.footprint).geometry)?,
+                );
             }
+            features.push(f);
         }
+        Ok(features)
+    }
 
-        cmds_applied
+    /// Returns the number of commands applied
+    pub fn import_commands(&mut self, transactions: Vec<Vec<Command>>) -> Result<usize> {
+        let old_commands = std::mem::take(&mut self.commands);
+        let old_redo_stack = std::mem::take(&mut self.redo_stack);
+        self.forget_snapshots();
+        self.restore_original();
+
+        let mut cmds_applied = 0;
+        for transaction in transactions {
+            self.start_transaction();
+            for cmd in transaction {
+                cmds_applied += 1;
+                if let Err(err) = self.validate_cmd(cmd).and_then(|_| self.push_cmd(cmd)) {
+                    // Restore the previous state
+                    self.finish_transaction();
+                    self.commands = old_commands;
+                    self.redo_stack = old_redo_stack;
+                    self.forget_snapshots();
+                    self.restore_from_snapshot()?;
+                    bail!("Command #{cmds_applied} ({cmd:?}) is invalid: {err}");
+                }
+            }
+            self.finish_transaction();
+        }
+        Ok(cmds_applied)
     }
-}
 
-impl RoadBundler {
-    pub fn apply_cmd(&mut self, cmd: Command) {
+    /// Check that the IDs referenced by a command exist right now
+    fn validate_cmd(&self, cmd: Command) -> Result<()> {
         match cmd {
+            Command::CollapseToCentroid(face) => {
+                if !self.faces.contains_key(&face) {
+                    bail!("{face:?} doesn't exist");
+                }
+            }
+            Command::CollapseDualCarriageway(face)
+            | Command::CollapseDualCarriagewayBySkeleton(face) => {
+                if !self.faces.contains_key(&face) {
+                    bail!("{face:?} doesn't exist");
+                }
+                if let Err(err) = self.check_dual_carriageway(face, cmd.center_line_method()) {
+                    bail!("face isn't a dual carriageway: {err}");
+                }
+            }
+            Command::RemoveEdge(edge) | Command::CollapseEdge(edge) => {
+                if !self.graph.edges.contains_key(&edge) {
+                    bail!("{edge:?} doesn't exist");
+                }
+            }
+            Command::CollapseDegenerateIntersection(i) => {
+                if !self.graph.intersections.contains_key(&i) {
+                    bail!("{i:?} doesn't exist");
+                }
+            }
+            Command::MergeAllSidepaths
+            | Command::MergeNearbySidepaths(_)
+            | Command::RemoveAllFootways
+            | Command::RemoveAllServiceRoads => {}
+        }
+        Ok(())
+    }
+
+    /// If the command fails, the graph and faces are left unchanged
+    pub fn apply_cmd(&mut self, cmd: Command) -> Result<()> {
+        self.graph.start_changes();
+        let result = match cmd {
             Command::CollapseToCentroid(face) => self.collapse_to_centroid(face),
-            Command::CollapseDualCarriageway(face) => self.collapse_dual_carriageway(face),
+            Command::CollapseDualCarriageway(face)
+            | Command::CollapseDualCarriagewayBySkeleton(face) => {
+                self.collapse_dual_carriageway(face, cmd.center_line_method())
+            }
             Command::CollapseEdge(edge) => self.collapse_edge(edge),
             Command::MergeAllSidepaths => self.merge_all_sidepaths(),
+            Command::MergeNearbySidepaths(params) => self.merge_nearby_sidepaths(params),
             Command::RemoveAllFootways => self.remove_all_footways(),
             Command::RemoveAllServiceRoads => self.remove_all_service_roads(),
             Command::RemoveEdge(edge) => self.remove_edge(edge),
             Command::CollapseDegenerateIntersection(i) => self.collapse_degenerate_intersection(i),
+        };
+        if let Err(err) = result {
+            self.graph.rollback_changes();
+            return Err(err);
         }
-        self.faces = make_faces(&self.graph, &self.areas);
+        let changes = self.graph.finish_changes();
+        update_faces(
+            &self.graph,
+            &self.areas,
+            &mut self.faces,
+            &mut self.index,
+            changes,
+        );
+        Ok(())
     }
 }
 
 // IDs are only meaningful when applied in the correct order
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Command {
     CollapseToCentroid(FaceID),
     CollapseDualCarriageway(FaceID),
+    /// Separate from `CollapseDualCarriageway` to keep reading commands saved before there was a
+    /// choice of center line
+    CollapseDualCarriagewayBySkeleton(FaceID),
     MergeAllSidepaths,
+    /// Separate from `MergeAllSidepaths` to keep replaying commands saved before paths that don't
+    /// enclose a face were matched
+    MergeNearbySidepaths(NearbySidepaths),
     RemoveAllFootways,
     RemoveEdge(EdgeID),
     RemoveAllServiceRoads,
@@ -325,10 +611,32 @@ pub enum Command {
     CollapseEdge(EdgeID),
 }
 
+impl Command {
+    pub fn collapse_dual_carriageway(face: FaceID, method: CenterLineMethod) -> Self {
+        match method {
+            CenterLineMethod::Average => Self::CollapseDualCarriageway(face),
+            CenterLineMethod::Skeleton => Self::CollapseDualCarriagewayBySkeleton(face),
+        }
+    }
+
+    /// Only meaningful for collapsing dual carriageways
+    fn center_line_method(&self) -> CenterLineMethod {
+        match self {
+            Self::CollapseDualCarriagewayBySkeleton(_) => CenterLineMethod::Skeleton,
+            _ => CenterLineMethod::Average,
+        }
+    }
+}
+
 fn err_to_js<E: std::fmt::Display>(err: E) -> JsValue {
     JsValue::from_str(&err.to_string())
 }
 
+fn parse_center_line_method(x: &str) -> Result<CenterLineMethod> {
+    serde_json::from_value(serde_json::Value::String(x.to_string()))
+        .with_context(|| format!("unknown center line method {x}"))
+}
+
 fn keep_edge(tags: &Tags) -> bool {
     if !tags.has("highway")
         || tags.is_any("highway", vec!["construction", "proposed"])
@@ -0,0 +1,359 @@
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use osm_reader::{NodeID, WayID};
+use serde::{Deserialize, Serialize};
+
+use crate::graph::OriginalEdgeID;
+use crate::{
+    Command, EdgeID, Face, FaceID, Graph, Intersection, IntersectionID, IntersectionProvenance,
+    NearbySidepaths, RoadBundler,
+};
+
+/// Like `Command`, but anchored to OSM IDs instead of IDs that only make sense in one session.
+/// These can be re-applied to a newer OSM extract of the same area.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum OsmCommand {
+    CollapseToCentroid(FaceAnchor),
+    CollapseDualCarriageway(FaceAnchor),
+    CollapseDualCarriagewayBySkeleton(FaceAnchor),
+    MergeAllSidepaths,
+    MergeNearbySidepaths(NearbySidepaths),
+    RemoveAllFootways,
+    RemoveEdge(EdgeAnchor),
+    RemoveAllServiceRoads,
+    CollapseDegenerateIntersection(NodeID),
+    CollapseEdge(EdgeAnchor),
+    /// For intersections created by an earlier edit, which have no OSM node
+    CollapseSyntheticIntersection(IntersectionAnchor),
+}
+
+/// One piece of an OSM way between two nodes, matching an `OriginalEdge`
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct OsmEdgeKey {
+    pub way: WayID,
+    pub node1: NodeID,
+    pub node2: NodeID,
+}
+
+/// All of the original OSM edges bundled into one edge
+pub type EdgeAnchor = BTreeSet<OsmEdgeKey>;
+/// All of the original OSM edges bundled into the boundary edges of one face
+pub type FaceAnchor = BTreeSet<OsmEdgeKey>;
+/// All of the original OSM edges bundled into the edges touching one intersection
+pub type IntersectionAnchor = BTreeSet<OsmEdgeKey>;
+
+/// The result of exporting `OsmCommand`s
+pub struct ExportReport {
+    pub transactions: Vec<Vec<OsmCommand>>,
+    /// Describes every edit that couldn't be expressed with OSM IDs
+    pub skipped: Vec<String>,
+}
+
+/// The result of importing `OsmCommand`s
+#[derive(Serialize)]
+pub struct ImportReport {
+    pub applied: usize,
+    /// Describes every edit that couldn't be applied
+    pub unresolved: Vec<String>,
+}
+
+impl RoadBundler {
+    /// Converts every command applied so far into an `OsmCommand`, keeping the same
+    /// transactions. This has to replay all commands from the start, to look up IDs at the time
+    /// each command was applied. Commands that can't be anchored to OSM IDs are skipped.
+    pub fn export_osm_commands(&mut self) -> Result<ExportReport> {
+        let transactions = self.commands.clone();
+        self.restore_original();
+
+        let mut report = ExportReport {
+            transactions: Vec::new(),
+            skipped: Vec::new(),
+        };
+        let mut num_cmds = 0;
+        for transaction in transactions {
+            let mut osm_transaction = Vec::new();
+            for cmd in transaction {
+                num_cmds += 1;
+                match self.anchor_cmd(cmd) {
+                    Ok(osm_cmd) => osm_transaction.push(osm_cmd),
+                    Err(err) => {
+                        report
+                            .skipped
+                            .push(format!("Edit #{num_cmds} ({cmd:?}): {err}"));
+                    }
+                }
+                if let Err(err) = self.apply_cmd(cmd) {
+                    self.restore_from_snapshot()?;
+                    bail!("Replaying command #{num_cmds} ({cmd:?}) failed: {err}");
+                }
+            }
+            if !osm_transaction.is_empty() {
+                report.transactions.push(osm_transaction);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Replaces the current edits with `OsmCommand`s, skipping any that no longer resolve to
+    /// something in the current area
+    pub fn import_osm_commands(&mut self, transactions: Vec<Vec<OsmCommand>>) -> ImportReport {
+        self.commands.clear();
+        self.redo_stack.clear();
+        self.forget_snapshots();
+        self.restore_original();
+
+        let mut report = ImportReport {
+            applied: 0,
+            unresolved: Vec::new(),
+        };
+        let mut num_cmds = 0;
+        for transaction in transactions {
+            self.start_transaction();
+            for osm_cmd in transaction {
+                num_cmds += 1;
+                match self.resolve_cmd(&osm_cmd).and_then(|cmd| {
+                    self.validate_cmd(cmd)?;
+                    self.push_cmd(cmd)
+                }) {
+                    Ok(()) => {
+                        report.applied += 1;
+                    }
+                    Err(err) => {
+                        report
+                            .unresolved
+                            .push(format!("Edit #{num_cmds} ({osm_cmd:?}): {err}"));
+                    }
+                }
+            }
+            self.finish_transaction();
+        }
+        report
+    }
+
+    fn anchor_cmd(&self, cmd: Command) -> Result<OsmCommand> {
+        Ok(match cmd {
+            Command::CollapseToCentroid(face) => {
+                OsmCommand::CollapseToCentroid(self.anchor_face(face)?)
+            }
+            Command::CollapseDualCarriageway(face) => {
+                OsmCommand::CollapseDualCarriageway(self.anchor_face(face)?)
+            }
+            Command::CollapseDualCarriagewayBySkeleton(face) => {
+                OsmCommand::CollapseDualCarriagewayBySkeleton(self.anchor_face(face)?)
+            }
+            Command::MergeAllSidepaths => OsmCommand::MergeAllSidepaths,
+            Command::MergeNearbySidepaths(params) => OsmCommand::MergeNearbySidepaths(params),
+            Command::RemoveAllFootways => OsmCommand::RemoveAllFootways,
+            Command::RemoveEdge(edge) => OsmCommand::RemoveEdge(self.anchor_edge(edge)?),
+            Command::RemoveAllServiceRoads => OsmCommand::RemoveAllServiceRoads,
+            Command::CollapseDegenerateIntersection(i) => {
+                let Some(intersection) = self.graph.intersections.get(&i) else {
+                    bail!("{i:?} doesn't exist");
+                };
+                match intersection.provenance {
+                    IntersectionProvenance::OSM(node) => {
+                        OsmCommand::CollapseDegenerateIntersection(node)
+                    }
+                    IntersectionProvenance::Synthetic => {
+                        OsmCommand::CollapseSyntheticIntersection(self.anchor_intersection(i)?)
+                    }
+                }
+            }
+            Command::CollapseEdge(edge) => OsmCommand::CollapseEdge(self.anchor_edge(edge)?),
+        })
+    }
+
+    fn resolve_cmd(&self, osm_cmd: &OsmCommand) -> Result<Command> {
+        Ok(match osm_cmd {
+            OsmCommand::CollapseToCentroid(anchor) => {
+                Command::CollapseToCentroid(self.resolve_face(anchor)?)
+            }
+            OsmCommand::CollapseDualCarriageway(anchor) => {
+                Command::CollapseDualCarriageway(self.resolve_face(anchor)?)
+            }
+            OsmCommand::CollapseDualCarriagewayBySkeleton(anchor) => {
+                Command::CollapseDualCarriagewayBySkeleton(self.resolve_face(anchor)?)
+            }
+            OsmCommand::MergeAllSidepaths => Command::MergeAllSidepaths,
+            OsmCommand::MergeNearbySidepaths(params) => Command::MergeNearbySidepaths(*params),
+            OsmCommand::RemoveAllFootways => Command::RemoveAllFootways,
+            OsmCommand::RemoveEdge(anchor) => Command::RemoveEdge(self.resolve_edge(anchor)?),
+            OsmCommand::RemoveAllServiceRoads => Command::RemoveAllServiceRoads,
+            OsmCommand::CollapseDegenerateIntersection(node) => {
+                Command::CollapseDegenerateIntersection(self.resolve_intersection(*node)?)
+            }
+            OsmCommand::CollapseEdge(anchor) => Command::CollapseEdge(self.resolve_edge(anchor)?),
+            OsmCommand::CollapseSyntheticIntersection(anchor) => {
+                Command::CollapseDegenerateIntersection(
+                    self.resolve_synthetic_intersection(anchor)?,
+                )
+            }
+        })
+    }
+
+    fn anchor_edge(&self, e: EdgeID) -> Result<EdgeAnchor> {
+        let Some(edge) = self.graph.edges.get(&e) else {
+            bail!("{e:?} doesn't exist");
+        };
+        let anchor = edge_keys(&self.graph, edge.kind.all_original_edges());
+        if anchor.is_empty() {
+            bail!("{e:?} isn't associated with any OSM ways");
+        }
+        Ok(anchor)
+    }
+
+    fn resolve_edge(&self, anchor: &EdgeAnchor) -> Result<EdgeID> {
+        let matches: Vec<EdgeID> = self
+            .graph
+            .edges
+            .values()
+            .filter(|edge| edge_keys(&self.graph, edge.kind.all_original_edges()) == *anchor)
+            .map(|edge| edge.id)
+            .collect();
+        match matches.len() {
+            0 => bail!("no edge matches these OSM ways anymore"),
+            1 => Ok(matches[0]),
+            n => bail!("{n} edges match these OSM ways"),
+        }
+    }
+
+    fn anchor_face(&self, id: FaceID) -> Result<FaceAnchor> {
+        let Some(face) = self.faces.get(&id) else {
+            bail!("{id:?} doesn't exist");
+        };
+        let anchor = face_keys(&self.graph, face);
+        if anchor.is_empty() {
+            bail!("the boundary of {id:?} isn't associated with any OSM ways");
+        }
+        Ok(anchor)
+    }
+
+    fn resolve_face(&self, anchor: &FaceAnchor) -> Result<FaceID> {
+        let matches: Vec<FaceID> = self
+            .faces
+            .iter()
+            .filter(|(_, face)| face_keys(&self.graph, face) == *anchor)
+            .map(|(id, _)| *id)
+            .collect();
+        match matches.len() {
+            0 => bail!("no face is bounded by these OSM ways anymore"),
+            1 => Ok(matches[0]),
+            n => bail!("{n} faces are bounded by these OSM ways"),
+        }
+    }
+
+    fn anchor_intersection(&self, i: IntersectionID) -> Result<IntersectionAnchor> {
+        let anchor = intersection_keys(&self.graph, &self.graph.intersections[&i]);
+        if anchor.is_empty() {
+            bail!("the edges touching {i:?} aren't associated with any OSM ways");
+        }
+        Ok(anchor)
+    }
+
+    fn resolve_intersection(&self, node: NodeID) -> Result<IntersectionID> {
+        self.graph
+            .intersections
+            .values()
+            .find(|i| matches!(i.provenance, IntersectionProvenance::OSM(n) if n == node))
+            .map(|i| i.id)
+            .ok_or_else(|| anyhow!("no intersection at OSM node {} anymore", node.0))
+    }
+
+    fn resolve_synthetic_intersection(
+        &self,
+        anchor: &IntersectionAnchor,
+    ) -> Result<IntersectionID> {
+        let matches: Vec<IntersectionID> = self
+            .graph
+            .intersections
+            .values()
+            .filter(|i| matches!(i.provenance, IntersectionProvenance::Synthetic))
+            .filter(|i| intersection_keys(&self.graph, i) == *anchor)
+            .map(|i| i.id)
+            .collect();
+        match matches.len() {
+            0 => bail!("no intersection touches these OSM ways anymore"),
+            1 => Ok(matches[0]),
+            n => bail!("{n} intersections touch these OSM ways"),
+        }
+    }
+}
+
+fn edge_keys(
+    graph: &Graph,
+    original_edges: impl IntoIterator<Item = OriginalEdgeID>,
+) -> BTreeSet<OsmEdgeKey> {
+    original_edges
+        .into_iter()
+        .map(|e| {
+            let orig = &graph.original_edges[&e];
+            OsmEdgeKey {
+                way: orig.way,
+                node1: orig.node1,
+                node2: orig.node2,
+            }
+        })
+        .collect()
+}
+
+fn intersection_keys(graph: &Graph, intersection: &Intersection) -> BTreeSet<OsmEdgeKey> {
+    edge_keys(
+        graph,
+        intersection
+            .edges
+            .iter()
+            .flat_map(|e| graph.edges[e].kind.all_original_edges()),
+    )
+}
+
+fn face_keys(graph: &Graph, face: &Face) -> BTreeSet<OsmEdgeKey> {
+    edge_keys(
+        graph,
+        face.boundary_edges
+            .iter()
+            .flat_map(|e| graph.edges[e].kind.all_original_edges()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::two_blocks;
+
+    #[test]
+    fn test_synthetic_intersections() {
+        let mut b = two_blocks();
+        // The left block is bounded by road C
+        let face = *b
+            .faces
+            .iter()
+            .find(|(_, face)| {
+                face.boundary_edges == vec![EdgeID(4), EdgeID(0), EdgeID(5), EdgeID(2)]
+            })
+            .unwrap()
+            .0;
+        b.do_cmd(Command::CollapseToCentroid(face)).unwrap();
+        let synthetic = b
+            .graph
+            .intersections
+            .values()
+            .find(|i| matches!(i.provenance, IntersectionProvenance::Synthetic))
+            .unwrap()
+            .id;
+        b.do_cmd(Command::CollapseDegenerateIntersection(synthetic))
+            .unwrap();
+
+        let report = b.export_osm_commands().unwrap();
+        assert!(report.skipped.is_empty(), "{:?}", report.skipped);
+        assert!(matches!(
+            report.transactions[1][0],
+            OsmCommand::CollapseSyntheticIntersection(_)
+        ));
+
+        let mut imported = two_blocks();
+        let import = imported.import_osm_commands(report.transactions);
+        assert_eq!(import.applied, 2);
+        assert!(import.unresolved.is_empty(), "{:?}", import.unresolved);
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::dual_carriageway::CenterLineMethod;
+use crate::{Command, NearbySidepaths, RoadBundler};
+
+/// An ordered list of bulk operations. Unlike a list of `Command`s, these don't reference any
+/// IDs, so the same recipe can be applied to any area.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Recipe {
+    pub steps: Vec<Step>,
+}
+
+/// Named the same as the methods in the web app
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "camelCase")]
+pub enum Step {
+    RemoveAllServiceRoads,
+    RemoveAllFootways,
+    MergeAllSidepaths,
+    /// Paths that don't enclose a face with a road, matched by how closely they follow it
+    MergeNearbySidepaths {
+        /// In meters, how far the path has to run alongside the road
+        #[serde(default = "default_min_sidepath_overlap")]
+        min_overlap: f64,
+        /// In meters, how far away from the road the path can be
+        #[serde(default = "default_max_sidepath_distance")]
+        max_distance: f64,
+        /// In meters, how far the path can stray from following the road
+        #[serde(default = "default_max_sidepath_frechet")]
+        max_frechet: f64,
+    },
+    FixAllDualCarriageways {
+        #[serde(default)]
+        center_line: CenterLineMethod,
+    },
+    FixAllRoundabouts,
+    FixAllDogLegs {
+        /// Only collapse dog-leg edges up to this length in meters
+        #[serde(default = "default_max_dog_leg_length")]
+        max_length: f64,
+    },
+    CollapseAllDegenerateIntersections,
+}
+
+fn default_max_dog_leg_length() -> f64 {
+    crate::dog_leg::DEFAULT_MAX_DOG_LEG_LENGTH
+}
+
+fn default_min_sidepath_overlap() -> f64 {
+    crate::sidepath::DEFAULT_MIN_SIDEPATH_OVERLAP
+}
+
+fn default_max_sidepath_distance() -> f64 {
+    crate::sidepath::DEFAULT_MAX_SIDEPATH_DISTANCE
+}
+
+fn default_max_sidepath_frechet() -> f64 {
+    crate::sidepath::DEFAULT_MAX_SIDEPATH_FRECHET
+}
+
+impl RoadBundler {
+    /// Returns the number of new commands applied. The whole recipe is undone as one unit. If a
+    /// step fails, the earlier steps stay applied.
+    pub fn apply_recipe(&mut self, recipe: &Recipe) -> Result<usize> {
+        let mut cmds_applied = 0;
+        self.start_transaction();
+        for step in &recipe.steps {
+            match self.apply_step(step) {
+                Ok(applied) => {
+                    info!("{step:?} applied {applied} commands");
+                    cmds_applied += applied;
+                }
+                Err(err) => {
+                    self.finish_transaction();
+                    bail!("{step:?} failed: {err}");
+                }
+            }
+        }
+        self.finish_transaction();
+        Ok(cmds_applied)
+    }
+
+    /// Returns the number of new commands applied
+    pub fn apply_step(&mut self, step: &Step) -> Result<usize> {
+        Ok(match step {
+            Step::RemoveAllServiceRoads => {
+                self.do_cmd(Command::RemoveAllServiceRoads)?;
+                1
+            }
+            Step::RemoveAllFootways => {
+                self.do_cmd(Command::RemoveAllFootways)?;
+                1
+            }
+            Step::MergeAllSidepaths => {
+                self.do_cmd(Command::MergeAllSidepaths)?;
+                1
+            }
+            Step::MergeNearbySidepaths {
+                min_overlap,
+                max_distance,
+                max_frechet,
+            } => {
+                self.do_cmd(Command::MergeNearbySidepaths(NearbySidepaths {
+                    min_overlap: *min_overlap,
+                    max_distance: *max_distance,
+                    max_frechet: *max_frechet,
+                }))?;
+                1
+            }
+            Step::FixAllDualCarriageways { center_line } => {
+                self.fix_all_dual_carriageways(*center_line)
+            }
+            Step::FixAllRoundabouts => self.fix_all_roundabouts(),
+            Step::FixAllDogLegs { max_length } => {
+                self.fix_all_dog_legs_with_max_length(*max_length)
+            }
+            Step::CollapseAllDegenerateIntersections => {
+                self.collapse_all_degenerate_intersections()
+            }
+        })
+    }
+}
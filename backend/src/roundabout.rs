@@ -0,0 +1,207 @@
+use std::collections::{BTreeSet, HashSet};
+use std::f64::consts::PI;
+use std::fmt;
+
+use geo::{Area, Euclidean, Length};
+use osm_reader::NodeID;
+use serde::Serialize;
+use utils::Tags;
+
+use crate::{Command, EdgeID, EdgeKind, Face, FaceKind, Graph, IntersectionID, RoadBundler};
+
+/// A circle is 1 and a square is about 0.79
+const MIN_CIRCULARITY: f64 = 0.7;
+/// Faces beside a mini-roundabout with at most this perimeter, in meters, are collapsed with it
+const MAX_MINI_ROUNDABOUT_PERIMETER: f64 = 60.0;
+
+#[derive(Serialize)]
+#[serde(tag = "kind")]
+pub enum Roundabout {
+    /// A ring of ways tagged as a roundabout
+    Ring { circularity: f64 },
+    /// A small face touching a node tagged `highway=mini_roundabout`, like a splitter island
+    /// where an approach divides into two oneways
+    Mini { node: NodeID },
+}
+
+/// Why a face isn't a roundabout
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "problem")]
+pub enum NotRoundabout {
+    WrongFaceKind {
+        kind: FaceKind,
+    },
+    /// Nothing bounds the face, like when it covers the whole map
+    NoEdges,
+    /// Some of the boundary isn't part of a roundabout, and the face isn't a small one beside a
+    /// mini-roundabout
+    NotRing {
+        other_edges: Vec<EdgeID>,
+    },
+    NotCircular {
+        circularity: f64,
+    },
+}
+
+impl fmt::Display for NotRoundabout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::WrongFaceKind { .. } => write!(f, "Face isn't a road artifact"),
+            Self::NoEdges => write!(f, "Face has no boundary edges"),
+            Self::NotRing { other_edges } => write!(
+                f,
+                "{} boundary edges aren't tagged as a roundabout",
+                other_edges.len()
+            ),
+            Self::NotCircular { circularity } => {
+                write!(f, "not circular enough ({circularity:.2})")
+            }
+        }
+    }
+}
+
+impl Roundabout {
+    pub fn maybe_new(
+        graph: &Graph,
+        face: &Face,
+        mini_roundabouts: &HashSet<NodeID>,
+    ) -> Result<Self, NotRoundabout> {
+        if face.kind != FaceKind::RoadArtifact {
+            return Err(NotRoundabout::WrongFaceKind {
+                kind: face.kind.clone(),
+            });
+        }
+
+        if face.boundary_edges.is_empty() {
+            return Err(NotRoundabout::NoEdges);
+        }
+
+        let other_edges: Vec<EdgeID> = face
+            .boundary_edges
+            .iter()
+            .filter(|e| !is_ring_edge(graph, &graph.edges[e].kind))
+            .cloned()
+            .collect();
+        let perimeter = Euclidean.length(face.polygon.exterior());
+        if !other_edges.is_empty() {
+            if perimeter <= MAX_MINI_ROUNDABOUT_PERIMETER {
+                if let Some(node) = face.boundary_intersections.iter().find_map(|i| {
+                    graph.intersections[i]
+                        .osm_nodes()
+                        .find(|n| mini_roundabouts.contains(n))
+                }) {
+                    return Ok(Self::Mini { node });
+                }
+            }
+            return Err(NotRoundabout::NotRing { other_edges });
+        }
+
+        // The isoperimetric quotient
+        let circularity = 4.0 * PI * face.polygon.unsigned_area() / perimeter.powi(2);
+        if circularity < MIN_CIRCULARITY {
+            return Err(NotRoundabout::NotCircular { circularity });
+        }
+
+        Ok(Self::Ring { circularity })
+    }
+}
+
+fn is_ring_edge(graph: &Graph, kind: &EdgeKind) -> bool {
+    match kind {
+        EdgeKind::Motorized { roads, .. } => {
+            !roads.is_empty()
+                && roads
+                    .iter()
+                    .all(|e| is_roundabout_way(&graph.original_edges[e].tags))
+        }
+        EdgeKind::Nonmotorized(_) => false,
+    }
+}
+
+fn is_roundabout_way(tags: &Tags) -> bool {
+    tags.is_any("junction", vec!["roundabout", "circular"])
+}
+
+impl RoadBundler {
+    /// Returns the number of new commands applied. Faces that fail to collapse are skipped.
+    /// Mini-roundabouts are already a single intersection, but any small faces around them are
+    /// collapsed into it.
+    pub fn fix_all_roundabouts(&mut self) -> usize {
+        let mut cmds_applied = 0;
+        let mut failed = BTreeSet::new();
+
+        self.start_transaction();
+        while let Some(id) = self.faces.iter().find_map(|(id, face)| {
+            (!failed.contains(id)
+                && Roundabout::maybe_new(&self.graph, face, &self.areas.mini_roundabouts).is_ok())
+            .then_some(*id)
+        }) {
+            match self.push_cmd(Command::CollapseToCentroid(id)) {
+                Ok(()) => cmds_applied += 1,
+                Err(err) => {
+                    warn!("Skipping roundabout {id:?}: {err}");
+                    failed.insert(id);
+                }
+            }
+        }
+        self.finish_transaction();
+
+        cmds_applied
+    }
+
+    /// Describes if an intersection is a mini-roundabout mapped as one OSM node, or a roundabout
+    /// that's been collapsed
+    pub fn intersection_roundabout(&self, i: IntersectionID) -> Option<&'static str> {
+        let intersection = &self.graph.intersections[&i];
+        if intersection
+            .osm_nodes()
+            .any(|n| self.areas.mini_roundabouts.contains(&n))
+        {
+            return Some("mini");
+        }
+        if intersection
+            .absorbed_edges
+            .iter()
+            .any(|e| is_roundabout_way(&self.graph.original_edges[e].tags))
+        {
+            return Some("collapsed");
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{face_containing, TestMap};
+
+    #[test]
+    fn test_collapse_mini_roundabout_islands() {
+        // Roads meeting at a mini-roundabout, with the southern approach split around a small
+        // island
+        let oneway = &[("highway", "primary"), ("oneway", "yes")];
+        let mut b = TestMap::default()
+            .road(&[(0.0, 100.0), (100.0, 100.0)], "West")
+            .road(&[(100.0, 100.0), (200.0, 100.0)], "East")
+            .road(&[(100.0, 200.0), (100.0, 120.0)], "South")
+            .way(&[(100.0, 120.0), (96.0, 110.0), (100.0, 100.0)], oneway)
+            .way(&[(100.0, 100.0), (104.0, 110.0), (100.0, 120.0)], oneway)
+            .mini_roundabout((100.0, 100.0))
+            .build();
+        let island = face_containing(&b, 100.0, 110.0);
+        assert!(matches!(
+            Roundabout::maybe_new(&b.graph, &b.faces[&island], &b.areas.mini_roundabouts),
+            Ok(Roundabout::Mini { .. })
+        ));
+
+        assert_eq!(b.fix_all_roundabouts(), 1);
+        assert!(!b.faces.contains_key(&island));
+        let minis = b
+            .graph
+            .intersections
+            .keys()
+            .filter(|i| b.intersection_roundabout(**i) == Some("mini"))
+            .count();
+        assert_eq!(minis, 1);
+    }
+}
@@ -1,10 +1,78 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
 use anyhow::Result;
+use geo::{Buffer, Euclidean, Length, LineString, Point};
 use geojson::GeoJson;
-use utils::Debugger;
+use rstar::primitives::GeomWithData;
+use rstar::{RTree, RTreeObject, AABB};
+use serde::{Deserialize, Serialize};
+use utils::{Debugger, Tags};
 
-use crate::geo_helpers::linestring_bearing;
+use crate::geo_helpers::{
+    fraction_range, linestring_bearing, roughly_parallel, runs_along, signed_offset,
+    SliceNearEndpoints, SliceNearestFrechetBoundary,
+};
+use crate::graph::OriginalEdgeID;
+use crate::kinds::{Side, SidepathPosition};
 use crate::{EdgeID, EdgeKind, Face, FaceKind, Graph, RoadBundler};
 
+/// A sidepath belongs to a road only if it runs alongside it for at least this many meters
+pub const DEFAULT_MIN_SIDEPATH_OVERLAP: f64 = 1.0;
+/// Paths that don't enclose a face with a road have to be within this many meters of it
+pub const DEFAULT_MAX_SIDEPATH_DISTANCE: f64 = 15.0;
+/// How far in meters a path that doesn't enclose a face can stray from following the road
+pub const DEFAULT_MAX_SIDEPATH_FRECHET: f64 = 5.0;
+
+/// How closely a path that doesn't enclose a face with a road has to follow it to be merged
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NearbySidepaths {
+    /// In meters, how far the path has to run alongside the road
+    pub min_overlap: f64,
+    /// In meters, how far away from the road the path can be
+    pub max_distance: f64,
+    /// In meters, how far the path can stray from following the road, by Fréchet distance
+    pub max_frechet: f64,
+}
+
+impl Default for NearbySidepaths {
+    fn default() -> Self {
+        Self {
+            min_overlap: DEFAULT_MIN_SIDEPATH_OVERLAP,
+            max_distance: DEFAULT_MAX_SIDEPATH_DISTANCE,
+            max_frechet: DEFAULT_MAX_SIDEPATH_FRECHET,
+        }
+    }
+}
+
+/// Why a face doesn't have a sidepath
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "problem")]
+pub enum NotSidepath {
+    WrongFaceKind {
+        kind: FaceKind,
+    },
+    /// Only crossings and other connectors are on the boundary
+    NoSidepathEdges {
+        connector_edges: Vec<EdgeID>,
+    },
+    NoParallelRoad {
+        sidepath_edges: Vec<EdgeID>,
+        road_edges: Vec<EdgeID>,
+    },
+}
+
+impl fmt::Display for NotSidepath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::WrongFaceKind { .. } => write!(f, "not a SidepathArtifact"),
+            Self::NoSidepathEdges { .. } => write!(f, "sidepath is missing"),
+            Self::NoParallelRoad { .. } => write!(f, "matching road is missing"),
+        }
+    }
+}
+
 struct Sidepath {
     sidepath_edges: Vec<EdgeID>,
     connector_edges: Vec<EdgeID>,
@@ -12,9 +80,11 @@ struct Sidepath {
 }
 
 impl Sidepath {
-    fn maybe_new(graph: &Graph, face: &Face) -> Result<Self> {
+    fn maybe_new(graph: &Graph, face: &Face) -> Result<Self, NotSidepath> {
         if face.kind != FaceKind::SidepathArtifact {
-            bail!("not a SidepathArtifact");
+            return Err(NotSidepath::WrongFaceKind {
+                kind: face.kind.clone(),
+            });
         }
 
         // Find the "main parts" of the sidepath -- not a crossing
@@ -29,11 +99,10 @@ impl Sidepath {
             match &edge.kind {
                 EdgeKind::Nonmotorized(edges) => {
                     // We shouldn't have a mix of crossings and not
-                    if edges.iter().all(|e| {
-                        let tags = &graph.original_edges[e].tags;
-                        tags.is_any("footway", vec!["crossing", "traffic_island"])
-                            || tags.is("cycleway", "crossing")
-                    }) {
+                    if edges
+                        .iter()
+                        .all(|e| is_crossing(&graph.original_edges[e].tags))
+                    {
                         connector_edges.push(*e);
                     } else {
                         sidepath_edges.push(*e);
@@ -47,10 +116,14 @@ impl Sidepath {
         }
 
         // And then filter the road edges so that they're parallel-ish to some part of the sidepath.
-        // We assume the entire road edge has the sidepath. If we need to be more precise later, we'll
-        // have to project endpoints and slice the line.
+        // merge_all_sidepaths later projects each piece of sidepath onto these roads, to find which
+        // parts of them it actually covers.
         //
         // TODO Do we need to do similar to turn some sidepath_edges into connector_edges?
+        if sidepath_edges.is_empty() {
+            return Err(NotSidepath::NoSidepathEdges { connector_edges });
+        }
+        let all_road_edges = road_edges.clone();
         road_edges.retain(|e| {
             let bearing = linestring_bearing(&graph.edges[e].linestring);
             sidepath_bearings
@@ -58,8 +131,11 @@ impl Sidepath {
                 .any(|b| roughly_parallel(bearing, *b))
         });
 
-        if sidepath_edges.is_empty() || road_edges.is_empty() {
-            bail!("sidepath or matching road is missing");
+        if road_edges.is_empty() {
+            return Err(NotSidepath::NoParallelRoad {
+                sidepath_edges,
+                road_edges: all_road_edges,
+            });
         }
 
         Ok(Self {
@@ -70,8 +146,13 @@ impl Sidepath {
     }
 }
 
+/// Explains why a face doesn't have a sidepath that `merge_all_sidepaths` would handle
+pub fn check_sidepath(graph: &Graph, face: &Face) -> Result<(), NotSidepath> {
+    Sidepath::maybe_new(graph, face).map(|_| ())
+}
+
 /// Return debug info about a sidepath
-pub fn detect_sidepath(graph: &Graph, face: &Face) -> Result<GeoJson> {
+pub fn detect_sidepath(graph: &Graph, face: &Face) -> Result<GeoJson, NotSidepath> {
     let sidepath = Sidepath::maybe_new(graph, face)?;
 
     let mut debug_hover = Debugger::new(graph.mercator.clone());
@@ -95,65 +176,289 @@ pub fn detect_sidepath(graph: &Graph, face: &Face) -> Result<GeoJson> {
 }
 
 impl RoadBundler {
-    pub fn merge_all_sidepaths(&mut self) {
+    pub fn merge_all_sidepaths(&mut self) -> Result<()> {
         // Make one pass using the faces, to update associations
+        let infos: Vec<Sidepath> = self
+            .faces
+            .values()
+            .filter_map(|face| Sidepath::maybe_new(&self.graph, face).ok())
+            .collect();
         let mut remove_edges = Vec::new();
-        for face in self.faces.values() {
-            if let Ok(info) = Sidepath::maybe_new(&self.graph, face) {
-                let mut original_sidepaths = Vec::new();
-                for e in info.sidepath_edges {
-                    remove_edges.push(e);
-
-                    match &self.graph.edges[&e].kind {
-                        EdgeKind::Nonmotorized(orig) => {
-                            original_sidepaths.extend(orig.clone());
-                        }
-                        _ => panic!("A sidepath was Motorized"),
+        for info in infos {
+            let mut original_sidepaths = Vec::new();
+            for e in info.sidepath_edges {
+                remove_edges.push(e);
+
+                match &self.graph.edges[&e].kind {
+                    EdgeKind::Nonmotorized(orig) => {
+                        original_sidepaths.extend(orig.clone());
                     }
+                    _ => bail!("Sidepath {e:?} is Motorized"),
                 }
+            }
 
-                let mut original_connectors = Vec::new();
-                for e in info.connector_edges {
-                    remove_edges.push(e);
+            let mut original_connectors = Vec::new();
+            for e in info.connector_edges {
+                remove_edges.push(e);
 
-                    match &self.graph.edges[&e].kind {
-                        EdgeKind::Nonmotorized(orig) => {
-                            original_connectors.extend(orig.clone());
-                        }
-                        _ => panic!("A sidepath is Motorized"),
+                match &self.graph.edges[&e].kind {
+                    EdgeKind::Nonmotorized(orig) => {
+                        original_connectors.extend(orig.clone());
                     }
+                    _ => bail!("Connector {e:?} is Motorized"),
                 }
+            }
 
-                // Create a many-to-many relationship -- every road will reference every piece of
-                // sidepath and connector. We could try some kind of linear referencing later to
-                // clean it up.
-                for e in info.road_edges {
-                    match self.graph.edges.get_mut(&e).unwrap().kind {
-                        EdgeKind::Motorized {
-                            ref mut sidepaths,
-                            ref mut connectors,
-                            ..
-                        } => {
-                            sidepaths.extend(original_sidepaths.clone());
-                            connectors.extend(original_connectors.clone());
-                        }
-                        _ => panic!("A road edge is Nonmotorized"),
-                    }
+            // Each piece of sidepath belongs to the roads it runs alongside. When it doesn't
+            // overlap any of them by much, fall back to every road in the face. Connectors make a
+            // many-to-many relationship.
+            for e in &info.road_edges {
+                if !self.graph.edges.contains_key(e) {
+                    bail!("Road {e:?} doesn't exist");
+                }
+            }
+            let mut sidepaths_per_road: BTreeMap<EdgeID, Vec<OriginalEdgeID>> = BTreeMap::new();
+            for s in &original_sidepaths {
+                let sidepath_ls = &self.original_graph.edges[&EdgeID(s.0)].linestring;
+                let mut overlapping: Vec<EdgeID> = info
+                    .road_edges
+                    .iter()
+                    .filter(|e| {
+                        let road_ls = &self.graph.edges[e].linestring;
+                        let (start, end) = fraction_range(road_ls, sidepath_ls);
+                        (end - start) * Euclidean.length(road_ls) >= DEFAULT_MIN_SIDEPATH_OVERLAP
+                    })
+                    .cloned()
+                    .collect();
+                if overlapping.is_empty() {
+                    overlapping = info.road_edges.clone();
                 }
+                for e in overlapping {
+                    sidepaths_per_road.entry(e).or_default().push(*s);
+                }
+            }
+
+            for e in info.road_edges {
+                let original_sidepaths = sidepaths_per_road.remove(&e).unwrap_or_default();
+                self.attach_sidepaths(e, original_sidepaths, &original_connectors)?;
             }
         }
 
         for e in remove_edges {
             // TODO Not sure why something is part of two SidepathArtifacts, but don't crash
             if self.graph.edges.contains_key(&e) {
-                self.graph.remove_edge(e);
+                self.graph.remove_edge(e)?;
+            }
+        }
+
+        self.graph.remove_all_empty_intersections();
+        Ok(())
+    }
+
+    /// Finds paths that don't enclose a face with a road, like sidewalks ending mid-block or
+    /// separated from the road by a hedge, by how closely they follow a road
+    pub fn merge_nearby_sidepaths(&mut self, params: NearbySidepaths) -> Result<()> {
+        let mut remove_edges = Vec::new();
+        for (path, roads) in self.match_sidepaths_without_faces(params) {
+            let EdgeKind::Nonmotorized(orig) = &self.graph.edges[&path].kind else {
+                bail!("Sidepath {path:?} is Motorized");
+            };
+            let original_sidepaths: Vec<OriginalEdgeID> = orig.iter().cloned().collect();
+            for e in roads {
+                self.attach_sidepaths(e, original_sidepaths.clone(), &[])?;
             }
+            remove_edges.push(path);
+        }
+        for e in remove_edges {
+            self.graph.remove_edge(e)?;
         }
 
         self.graph.remove_all_empty_intersections();
+        Ok(())
+    }
+
+    /// Associates pieces of sidepath and connectors with a road edge
+    fn attach_sidepaths(
+        &mut self,
+        e: EdgeID,
+        original_sidepaths: Vec<OriginalEdgeID>,
+        original_connectors: &[OriginalEdgeID],
+    ) -> Result<()> {
+        let Some(edge) = self.graph.edges.get(&e) else {
+            bail!("Road {e:?} doesn't exist");
+        };
+        let positions: Vec<_> = original_sidepaths
+            .iter()
+            .filter_map(|s| Some((*s, self.sidepath_position(&edge.kind, *s)?)))
+            .collect();
+
+        match self.graph.edge_mut(e).unwrap().kind {
+            EdgeKind::Motorized {
+                ref mut sidepaths,
+                ref mut sidepath_positions,
+                ref mut connectors,
+                ..
+            } => {
+                sidepaths.extend(original_sidepaths);
+                sidepath_positions.extend(positions);
+                connectors.extend(original_connectors.iter().cloned());
+                Ok(())
+            }
+            _ => bail!("Road {e:?} is Nonmotorized"),
+        }
+    }
+
+    /// Matches footways and cycleways to the roads they run alongside, without using faces.
+    /// Returns each path edge and the road edges it belongs to.
+    fn match_sidepaths_without_faces(&self, params: NearbySidepaths) -> Vec<(EdgeID, Vec<EdgeID>)> {
+        let rtree = RTree::bulk_load(
+            self.graph
+                .edges
+                .values()
+                .filter(|edge| matches!(&edge.kind, EdgeKind::Motorized { roads, .. } if !roads.is_empty()))
+                .map(|edge| GeomWithData::new(edge.linestring.clone(), edge.id))
+                .collect(),
+        );
+
+        let mut matches = Vec::new();
+        for edge in self.graph.edges.values() {
+            let EdgeKind::Nonmotorized(orig) = &edge.kind else {
+                continue;
+            };
+            if !orig
+                .iter()
+                .all(|e| is_sidepath_candidate(&self.graph.original_edges[e].tags))
+            {
+                continue;
+            }
+
+            let envelope = edge.linestring.envelope();
+            let search = AABB::from_corners(
+                Point::new(
+                    envelope.lower().x() - params.max_distance,
+                    envelope.lower().y() - params.max_distance,
+                ),
+                Point::new(
+                    envelope.upper().x() + params.max_distance,
+                    envelope.upper().y() + params.max_distance,
+                ),
+            );
+            let mut roads = Vec::new();
+            let mut alongside = 0.0;
+            for road in rtree.locate_in_envelope_intersecting(&search) {
+                if let Some(length) = follows_road(road.geom(), &edge.linestring, params) {
+                    roads.push(road.data);
+                    alongside += length;
+                }
+            }
+            // Most of the path has to follow some road
+            if !roads.is_empty() && alongside >= 0.5 * Euclidean.length(&edge.linestring) {
+                matches.push((edge.id, roads));
+            }
+        }
+        matches
+    }
+
+    /// Finds the piece of road a sidepath is closest to, and which side of it the sidepath is on
+    fn sidepath_position(
+        &self,
+        road_kind: &EdgeKind,
+        sidepath: OriginalEdgeID,
+    ) -> Option<SidepathPosition> {
+        let EdgeKind::Motorized {
+            roads,
+            service_roads,
+            ..
+        } = road_kind
+        else {
+            return None;
+        };
+        let sidepath_ls = &self.original_graph.edges[&EdgeID(sidepath.0)].linestring;
+        roads
+            .iter()
+            .chain(service_roads)
+            .map(|road| {
+                let road_ls = &self.original_graph.edges[&EdgeID(road.0)].linestring;
+                (*road, signed_offset(road_ls, sidepath_ls))
+            })
+            .min_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+            .map(|(road, offset)| SidepathPosition {
+                road,
+                side: if offset >= 0.0 {
+                    Side::Right
+                } else {
+                    Side::Left
+                },
+                offset: offset.abs(),
+            })
     }
 
-    pub fn remove_all_footways(&mut self) {
+    /// Which side of an edge a bundled sidepath is on and how far away, looking along the edge.
+    /// Positive is to the right.
+    pub fn sidepath_offset(&self, e: EdgeID, sidepath: OriginalEdgeID) -> Option<f64> {
+        let edge = self.graph.edges.get(&e)?;
+        let EdgeKind::Motorized {
+            sidepath_positions, ..
+        } = &edge.kind
+        else {
+            return None;
+        };
+        let position = sidepath_positions.get(&sidepath)?;
+        let road_ls = &self.original_graph.edges[&EdgeID(position.road.0)].linestring;
+        let side = if runs_along(&edge.linestring, road_ls) {
+            position.side
+        } else {
+            position.side.opposite()
+        };
+        let from_road = match side {
+            Side::Left => -position.offset,
+            Side::Right => position.offset,
+        };
+        // The road might not be where the edge is now, like one carriageway of a collapsed dual
+        // carriageway. Only the part of the road beside the sidepath counts.
+        let sidepath_ls = &self.original_graph.edges[&EdgeID(sidepath.0)].linestring;
+        let road_beside = road_ls.slice_near_endpoints(sidepath_ls);
+        Some(signed_offset(&edge.linestring, &road_beside) + from_road)
+    }
+
+    /// Where a bundled sidepath runs along an edge, as sorted fractions of the edge's length.
+    /// This comes from the original geometry, so it stays valid as edges are merged and reversed.
+    pub fn sidepath_range(&self, e: EdgeID, sidepath: OriginalEdgeID) -> Option<(f64, f64)> {
+        let edge = self.graph.edges.get(&e)?;
+        let EdgeKind::Motorized { sidepaths, .. } = &edge.kind else {
+            return None;
+        };
+        if !sidepaths.contains(&sidepath) {
+            return None;
+        }
+        Some(fraction_range(
+            &edge.linestring,
+            &self.original_graph.edges[&EdgeID(sidepath.0)].linestring,
+        ))
+    }
+
+    /// The sidepaths bundled into an edge that run alongside some distance in meters from its
+    /// start
+    pub fn sidepaths_at(&self, e: EdgeID, distance: f64) -> Vec<OriginalEdgeID> {
+        let Some(edge) = self.graph.edges.get(&e) else {
+            return Vec::new();
+        };
+        let EdgeKind::Motorized { sidepaths, .. } = &edge.kind else {
+            return Vec::new();
+        };
+        let length = Euclidean.length(&edge.linestring);
+        sidepaths
+            .iter()
+            .filter(|s| {
+                self.sidepath_range(e, **s).is_some_and(|(start, end)| {
+                    start * length <= distance && distance <= end * length
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn remove_all_footways(&mut self) -> Result<()> {
         let mut remove_edges = Vec::new();
         for edge in self.graph.edges.values() {
             match &edge.kind {
@@ -171,32 +476,157 @@ impl RoadBundler {
         }
 
         for e in remove_edges {
-            self.graph.remove_edge(e);
+            self.graph.remove_edge(e)?;
         }
 
         self.graph.remove_all_empty_intersections();
+        Ok(())
     }
 }
 
-// or anti-parallel
-fn roughly_parallel(b1: f64, b2: f64) -> bool {
-    let diff = ((b1 - b2 + 180.0).abs() % 360.0 - 180.0).abs();
-    let tolerance = 30.0;
-    diff < tolerance || (diff - 180.0).abs() < tolerance
+/// If part of a path runs alongside a road, returns how long that part is. The path has to be
+/// roughly parallel and close to the road, and follow it closely, judging by the Fréchet distance
+/// to the road buffered out to the path's average offset.
+fn follows_road(road: &LineString, path: &LineString, params: NearbySidepaths) -> Option<f64> {
+    let path_beside = path.slice_near_endpoints(road);
+    let length = Euclidean.length(&path_beside);
+    if length < params.min_overlap {
+        return None;
+    }
+    let road_beside = road.slice_near_endpoints(&path_beside);
+    if !roughly_parallel(
+        linestring_bearing(&road_beside),
+        linestring_bearing(&path_beside),
+    ) {
+        return None;
+    }
+
+    let offset = signed_offset(&road_beside, &path_beside).abs();
+    if offset > params.max_distance {
+        return None;
+    }
+    let buffered = road_beside.buffer(offset).0.into_iter().next()?;
+    let (_, frechet) = buffered.slice_nearest_frechet_boundary(&path_beside);
+    if frechet > params.max_frechet {
+        return None;
+    }
+    Some(length)
+}
+
+fn is_crossing(tags: &Tags) -> bool {
+    tags.is_any("footway", vec!["crossing", "traffic_island"]) || tags.is("cycleway", "crossing")
+}
+
+/// Could this path be a sidepath, if it's alongside a road?
+fn is_sidepath_candidate(tags: &Tags) -> bool {
+    tags.is_any("highway", vec!["footway", "cycleway", "path"])
+        && !is_crossing(tags)
+        && !tags.is("is_sidepath", "no")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_helpers::{crossed_dc, edge_near, face_containing};
+    use crate::Command;
+
+    #[test]
+    fn test_sidepath_offset_after_collapsing_dc() {
+        // A sidewalk 5m north of the northern carriageway
+        let sidewalk = LineString::from(vec![(30.0, -5.0), (90.0, -5.0)]);
+        let mut b = crossed_dc()
+            .way(&[(30.0, -5.0), (90.0, -5.0)], &[("highway", "footway")])
+            .build();
+        b.do_cmd(Command::MergeNearbySidepaths(NearbySidepaths::default()))
+            .unwrap();
+        let id = face_containing(&b, 50.0, 10.0);
+        b.do_cmd(Command::CollapseDualCarriageway(id)).unwrap();
+
+        // The center line is 15m from the sidewalk
+        let e = edge_near(&b, 50.0, 10.0);
+        let sidepath = OriginalEdgeID(b.original_graph.edges.len() - 1);
+        let EdgeKind::Motorized { sidepaths, .. } = &b.graph.edges[&e].kind else {
+            panic!("{e:?} isn't a road");
+        };
+        assert!(sidepaths.contains(&sidepath));
+        // And nothing else
+        for edge in b.graph.edges.values() {
+            assert!(
+                edge.id == e || !edge.kind.all_original_edges().contains(&sidepath),
+                "{:?} also has the sidewalk",
+                edge.id
+            );
+        }
+        let got = b.sidepath_offset(e, sidepath).unwrap();
+        let expected = signed_offset(&b.graph.edges[&e].linestring, &sidewalk);
+        assert!((expected.abs() - 15.0).abs() < 1.0, "{expected}");
+        assert!((got - expected).abs() < 1.0, "got {got}, want {expected}");
+
+        // The sidewalk is outside both carriageways in the cross-section
+        let components = b.get_cross_section(e).unwrap().components;
+        let outermost = if got < 0.0 {
+            components.first()
+        } else {
+            components.last()
+        };
+        assert!(matches!(
+            outermost.unwrap().kind,
+            crate::ComponentKind::Sidewalk
+        ));
+    }
 
     #[test]
-    fn test_roughly_parallel() {
-        assert!(roughly_parallel(359., 360.));
-        assert!(roughly_parallel(359., 0.));
-        assert!(roughly_parallel(354., 2.));
-        assert!(roughly_parallel(179., 359.));
-
-        assert!(!roughly_parallel(179., 271.));
-        // south vs west
+    fn test_merge_nearby_sidepaths() {
+        // A sidewalk 5m from the northern carriageway, not connected to anything
+        let map = || {
+            crossed_dc()
+                .way(&[(30.0, -5.0), (90.0, -5.0)], &[("highway", "footway")])
+                .build()
+        };
+        let num_edges = map().graph.edges.len();
+
+        // Without a face, the older command doesn't find it
+        let mut b = map();
+        b.do_cmd(Command::MergeAllSidepaths).unwrap();
+        assert_eq!(b.graph.edges.len(), num_edges);
+
+        let mut b = map();
+        b.do_cmd(Command::MergeNearbySidepaths(NearbySidepaths {
+            max_distance: 3.0,
+            ..Default::default()
+        }))
+        .unwrap();
+        assert_eq!(b.graph.edges.len(), num_edges);
+
+        let mut b = map();
+        b.do_cmd(Command::MergeNearbySidepaths(NearbySidepaths::default()))
+            .unwrap();
+        assert_eq!(b.graph.edges.len(), num_edges - 1);
+    }
+
+    #[test]
+    fn test_follows_road() {
+        let params = NearbySidepaths::default();
+        let road = LineString::from(vec![(0.0, 0.0), (100.0, 0.0)]);
+
+        // A sidewalk ending mid-block
+        let sidewalk = LineString::from(vec![(10.0, 5.0), (60.0, 5.0)]);
+        let length = follows_road(&road, &sidewalk, params).unwrap();
+        assert!((length - 50.0).abs() < 1e-6);
+
+        // Only the part alongside the road counts
+        let longer = LineString::from(vec![(50.0, -5.0), (150.0, -5.0)]);
+        let length = follows_road(&road, &longer, params).unwrap();
+        assert!((length - 50.0).abs() < 1e-6);
+
+        let far = LineString::from(vec![(10.0, 40.0), (60.0, 40.0)]);
+        assert!(follows_road(&road, &far, params).is_none());
+
+        let perpendicular = LineString::from(vec![(50.0, 5.0), (50.0, 14.0)]);
+        assert!(follows_road(&road, &perpendicular, params).is_none());
+
+        // Heads away from the road and comes back
+        let detour = LineString::from(vec![(10.0, 3.0), (35.0, 14.0), (60.0, 3.0)]);
+        assert!(follows_road(&road, &detour, params).is_none());
     }
 }
@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use itertools::Itertools;
+use osm_reader::{Element, NodeID, WayID};
+use utils::Tags;
+
+use crate::graph::OriginalEdgeID;
+use crate::{EdgeKind, RoadBundler};
+
+impl RoadBundler {
+    /// For every footway and cycleway bundled as a sidepath, the `is_sidepath` tags to add to its
+    /// OSM way, describing the road it's beside. Tags already on a way are left alone.
+    pub fn sidepath_tag_suggestions(&self) -> BTreeMap<WayID, Tags> {
+        // For each sidepath way, the pieces of road it's beside
+        let mut roads_per_way: BTreeMap<WayID, (OriginalEdgeID, Vec<OriginalEdgeID>)> =
+            BTreeMap::new();
+        for edge in self.graph.edges.values() {
+            let EdgeKind::Motorized {
+                roads,
+                sidepaths,
+                sidepath_positions,
+                ..
+            } = &edge.kind
+            else {
+                continue;
+            };
+            for s in sidepaths {
+                let road = sidepath_positions
+                    .get(s)
+                    .map(|position| position.road)
+                    .or_else(|| roads.iter().next().cloned());
+                let entry = roads_per_way
+                    .entry(self.graph.original_edges[s].way)
+                    .or_insert((*s, Vec::new()));
+                entry.1.extend(road);
+            }
+        }
+
+        let mut suggestions = BTreeMap::new();
+        for (way, (piece, roads)) in roads_per_way {
+            let most_common = |key| {
+                roads
+                    .iter()
+                    .filter_map(|e| self.graph.original_edges[e].tags.get(key))
+                    .counts()
+                    .into_iter()
+                    .max_by_key(|(value, count)| (*count, *value))
+                    .map(|(value, _)| value)
+            };
+            if let Some(tags) = suggest_tags(
+                &self.graph.original_edges[&piece].tags,
+                most_common("highway"),
+                most_common("name"),
+            ) {
+                suggestions.insert(way, tags);
+            }
+        }
+        suggestions
+    }
+
+    /// Writes `sidepath_tag_suggestions` as a JOSM .osm file, marking each way with
+    /// `action="modify"` and giving it its original tags plus the new ones. Versions and the
+    /// nodes of ways aren't kept after loading, so they're read again from `input_bytes`, which
+    /// must be the same input. Fails if the input doesn't record versions, since JOSM couldn't
+    /// upload the changes.
+    pub fn sidepath_tags_to_josm_osm(&self, input_bytes: &[u8]) -> Result<String> {
+        let suggestions = self.sidepath_tag_suggestions();
+        let elements = OsmElements::read(input_bytes, suggestions.keys().cloned().collect())?;
+        self.write_josm_osm(suggestions, &elements)
+    }
+
+    fn write_josm_osm(
+        &self,
+        suggestions: BTreeMap<WayID, Tags>,
+        elements: &OsmElements,
+    ) -> Result<String> {
+        let tags_per_way: BTreeMap<WayID, &Tags> = self
+            .graph
+            .original_edges
+            .values()
+            .map(|e| (e.way, &e.tags))
+            .collect();
+
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<osm version=\"0.6\" generator=\"road-bundler\">\n");
+        let mut nodes_written = BTreeSet::new();
+        let mut ways = String::new();
+        for (way, new_tags) in suggestions {
+            let Some((version, node_ids)) = elements.ways.get(&way) else {
+                bail!("{way:?} isn't in the input");
+            };
+            let Some(version) = version else {
+                bail!("{}", missing_version(format!("way {}", way.0)));
+            };
+
+            for node in node_ids {
+                if !nodes_written.insert(*node) {
+                    continue;
+                }
+                let Some((version, lon, lat)) = elements.nodes.get(node) else {
+                    bail!("{node:?} isn't in the input");
+                };
+                let Some(version) = version else {
+                    bail!("{}", missing_version(format!("node {}", node.0)));
+                };
+                out.push_str(&format!(
+                    "  <node id=\"{}\" version=\"{version}\" lat=\"{lat}\" lon=\"{lon}\"/>\n",
+                    node.0
+                ));
+            }
+
+            ways.push_str(&format!(
+                "  <way id=\"{}\" action=\"modify\" version=\"{version}\">\n",
+                way.0
+            ));
+            for node in node_ids {
+                ways.push_str(&format!("    <nd ref=\"{}\"/>\n", node.0));
+            }
+            let all_tags: BTreeMap<&String, &String> = tags_per_way[&way]
+                .0
+                .iter()
+                .chain(new_tags.0.iter())
+                .collect();
+            for (key, value) in all_tags {
+                ways.push_str(&format!(
+                    "    <tag k=\"{}\" v=\"{}\"/>\n",
+                    escape(key),
+                    escape(value)
+                ));
+            }
+            ways.push_str("  </way>\n");
+        }
+        out.push_str(&ways);
+        out.push_str("</osm>\n");
+        Ok(out)
+    }
+}
+
+/// The parts of some OSM ways and their nodes needed to modify them in JOSM
+#[derive(Default)]
+struct OsmElements {
+    /// The version and nodes of each way
+    ways: BTreeMap<WayID, (Option<i32>, Vec<NodeID>)>,
+    /// The version, longitude, and latitude of each node
+    nodes: BTreeMap<NodeID, (Option<i32>, f64, f64)>,
+}
+
+impl OsmElements {
+    /// Nodes come before ways in OSM files, so this reads the input twice: once for the ways,
+    /// then for their nodes.
+    fn read(input_bytes: &[u8], ways: BTreeSet<WayID>) -> Result<Self> {
+        let mut elements = Self::default();
+        if ways.is_empty() {
+            return Ok(elements);
+        }
+
+        osm_reader::parse(input_bytes, |elem| {
+            if let Element::Way {
+                id,
+                node_ids,
+                version,
+                ..
+            } = elem
+            {
+                if ways.contains(&id) {
+                    elements.ways.insert(id, (version, node_ids));
+                }
+            }
+        })?;
+
+        let nodes: BTreeSet<NodeID> = elements
+            .ways
+            .values()
+            .flat_map(|(_, node_ids)| node_ids.iter().cloned())
+            .collect();
+        osm_reader::parse(input_bytes, |elem| {
+            if let Element::Node {
+                id,
+                lon,
+                lat,
+                version,
+                ..
+            } = elem
+            {
+                if nodes.contains(&id) {
+                    elements.nodes.insert(id, (version, lon, lat));
+                }
+            }
+        })?;
+        Ok(elements)
+    }
+}
+
+fn missing_version(element: String) -> String {
+    format!(
+        "The input doesn't record the version of {element}, so JOSM couldn't upload changes to \
+         it. Use an extract with metadata, like one from the OSM API or Overpass with `out meta`."
+    )
+}
+
+/// The `is_sidepath` tags missing from a path, given the highway class and name of the road it's
+/// beside. Returns `None` if there's nothing to add, the path is explicitly not a sidepath, or
+/// it's already tagged as beside a different class of road, which a mapper should check by hand.
+fn suggest_tags(existing: &Tags, highway: Option<&String>, name: Option<&String>) -> Option<Tags> {
+    if existing.is("is_sidepath", "no") {
+        return None;
+    }
+    if let (Some(tagged), Some(highway)) = (existing.get("is_sidepath:of"), highway) {
+        if tagged != highway {
+            return None;
+        }
+    }
+    let mut tags = Tags::default();
+    for (key, value) in [
+        ("is_sidepath", Some(&"yes".to_string())),
+        ("is_sidepath:of", highway),
+        ("is_sidepath:of:name", name),
+    ] {
+        if let Some(value) = value {
+            if !existing.has(key) {
+                tags.insert(key, value.clone());
+            }
+        }
+    }
+    if tags.0.is_empty() {
+        None
+    } else {
+        Some(tags)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::crossed_dc;
+    use crate::{Command, NearbySidepaths};
+
+    #[test]
+    fn test_josm_osm() {
+        // A sidewalk 5m from the northern carriageway
+        let mut b = crossed_dc()
+            .way(&[(30.0, -5.0), (90.0, -5.0)], &[("highway", "footway")])
+            .build();
+        b.do_cmd(Command::MergeNearbySidepaths(NearbySidepaths::default()))
+            .unwrap();
+        let suggestions = b.sidepath_tag_suggestions();
+        assert_eq!(suggestions.keys().collect::<Vec<_>>(), vec![&WayID(6)]);
+
+        let mut elements = OsmElements::default();
+        elements
+            .ways
+            .insert(WayID(6), (Some(3), vec![NodeID(11), NodeID(12)]));
+        elements.nodes.insert(NodeID(11), (Some(1), 0.1, 51.5));
+        elements.nodes.insert(NodeID(12), (None, 0.2, 51.5));
+
+        // Every node needs a version too
+        let err = b
+            .write_josm_osm(suggestions.clone(), &elements)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("doesn't record the version of node 12"),
+            "{err}"
+        );
+
+        elements.nodes.insert(NodeID(12), (Some(2), 0.2, 51.5));
+        let osm = b.write_josm_osm(suggestions, &elements).unwrap();
+        assert!(osm.contains("<node id=\"12\" version=\"2\" lat=\"51.5\" lon=\"0.2\"/>"));
+        assert!(osm.contains("<way id=\"6\" action=\"modify\" version=\"3\">"));
+        assert!(osm.contains("<tag k=\"highway\" v=\"footway\"/>"));
+        assert!(osm.contains("<tag k=\"is_sidepath:of:name\" v=\"Main Street\"/>"));
+    }
+
+    #[test]
+    fn test_suggest_tags() {
+        let highway = "primary".to_string();
+        let name = "Main Street".to_string();
+
+        let mut sidewalk = Tags::default();
+        sidewalk.insert("highway", "footway");
+        sidewalk.insert("footway", "sidewalk");
+        let tags = suggest_tags(&sidewalk, Some(&highway), Some(&name)).unwrap();
+        assert_eq!(tags.get("is_sidepath").unwrap(), "yes");
+        assert_eq!(tags.get("is_sidepath:of").unwrap(), "primary");
+        assert_eq!(tags.get("is_sidepath:of:name").unwrap(), "Main Street");
+
+        // Unnamed roads don't get a name
+        let tags = suggest_tags(&sidewalk, Some(&highway), None).unwrap();
+        assert!(!tags.has("is_sidepath:of:name"));
+
+        // Existing tags aren't changed
+        let mut tagged = sidewalk.clone();
+        tagged.insert("is_sidepath", "yes");
+        tagged.insert("is_sidepath:of", "primary");
+        let tags = suggest_tags(&tagged, Some(&highway), Some(&name)).unwrap();
+        assert!(!tags.has("is_sidepath"));
+        assert!(!tags.has("is_sidepath:of"));
+        assert_eq!(tags.get("is_sidepath:of:name").unwrap(), "Main Street");
+
+        tagged.insert("is_sidepath:of:name", "Main Street");
+        assert!(suggest_tags(&tagged, Some(&highway), Some(&name)).is_none());
+
+        // Beside a different class of road than inferred, so the name probably doesn't match either
+        let mut conflicting = sidewalk.clone();
+        conflicting.insert("is_sidepath:of", "secondary");
+        assert!(suggest_tags(&conflicting, Some(&highway), Some(&name)).is_none());
+
+        let mut not_sidepath = sidewalk.clone();
+        not_sidepath.insert("is_sidepath", "no");
+        assert!(suggest_tags(&not_sidepath, Some(&highway), Some(&name)).is_none());
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape("Tom & Jerry's \"<Lane>\""),
+            "Tom &amp; Jerry&apos;s &quot;&lt;Lane&gt;&quot;"
+        );
+    }
+}
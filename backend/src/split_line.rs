@@ -1,3 +1,4 @@
+use anyhow::{Context, Result};
 use geo::{Closest, ClosestPoint, LineLocatePoint, LineString, Point};
 use utils::LineSplit;
 
@@ -9,7 +10,7 @@ pub struct Splits {
     pub new_endpts: Vec<Point>,
 }
 
-pub fn split_center(graph: &Graph, center_line: &LineString, face: &Face) -> Splits {
+pub fn split_center(graph: &Graph, center_line: &LineString, face: &Face) -> Result<Splits> {
     // Where does this new center need to be split, based on connecting edges? Side roads will be
     // properly in the middle, and connections (maybe resolved by first merging the faces) will be
     // at 0 or 1.
@@ -26,7 +27,7 @@ pub fn split_center(graph: &Graph, center_line: &LineString, face: &Face) -> Spl
                 Closest::SinglePoint(pt) => pt,
                 Closest::Indeterminate => {
                     // TODO Possible?
-                    panic!("closest_point for split_center is Indeterminate");
+                    bail!("closest_point for split_center is Indeterminate");
                 }
             };
 
@@ -37,21 +38,23 @@ pub fn split_center(graph: &Graph, center_line: &LineString, face: &Face) -> Spl
     split_fractions.dedup();
     let lines: Vec<LineString> = center_line
         .line_split_many(&split_fractions)
-        .unwrap()
+        .context("couldn't split center line")?
         .into_iter()
         .flatten()
         .collect();
-    let new_endpts = linestring_endpoints(&lines);
+    let new_endpts = linestring_endpoints(&lines)?;
 
-    Splits { lines, new_endpts }
+    Ok(Splits { lines, new_endpts })
 }
 
-fn linestring_endpoints(lines: &Vec<LineString>) -> Vec<Point> {
+fn linestring_endpoints(lines: &Vec<LineString>) -> Result<Vec<Point>> {
     let mut pts = Vec::new();
     for line in lines {
         pts.push(line.0[0].into());
     }
-    let last_line = lines.last().unwrap();
+    let last_line = lines
+        .last()
+        .context("splitting center line produced nothing")?;
     pts.push(last_line.0[last_line.0.len() - 1].into());
-    pts
+    Ok(pts)
 }
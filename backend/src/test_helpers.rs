@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, HashMap};
+
+use geo::{Area, Contains, Coord, Distance, Euclidean, LineString, Point, Rect};
+use osm_reader::{NodeID, WayID};
+use utils::osm2graph::{self, OsmReader};
+use utils::{Mercator, Tags};
+
+use crate::areas::ReadOsmAreas;
+use crate::{EdgeID, FaceID, RoadBundler};
+
+/// Builds a small map for tests without parsing OSM data. Points are in meters, with Y pointing
+/// down like the rest of the world-space geometry. Ways are split into edges wherever they share
+/// a point, like osm2graph does.
+#[derive(Default)]
+pub struct TestMap {
+    ways: Vec<(Vec<Coord>, Tags)>,
+    mini_roundabouts: Vec<Coord>,
+}
+
+impl TestMap {
+    pub fn way(mut self, pts: &[(f64, f64)], tags: &[(&str, &str)]) -> Self {
+        let mut way_tags = Tags::default();
+        for (k, v) in tags {
+            way_tags.insert(*k, *v);
+        }
+        self.ways.push((
+            pts.iter().map(|(x, y)| Coord { x: *x, y: *y }).collect(),
+            way_tags,
+        ));
+        self
+    }
+
+    pub fn road(self, pts: &[(f64, f64)], name: &str) -> Self {
+        self.way(pts, &[("highway", "primary"), ("name", name)])
+    }
+
+    pub fn oneway(self, pts: &[(f64, f64)], name: &str) -> Self {
+        self.way(
+            pts,
+            &[("highway", "primary"), ("name", name), ("oneway", "yes")],
+        )
+    }
+
+    /// Tags the node at this point, which must be on some way, as `highway=mini_roundabout`
+    pub fn mini_roundabout(mut self, pt: (f64, f64)) -> Self {
+        self.mini_roundabouts.push(Coord { x: pt.0, y: pt.1 });
+        self
+    }
+
+    pub fn build(self) -> RoadBundler {
+        // About 1km square, comfortably containing everything
+        let mercator = Mercator::from(Rect::new(
+            Coord { x: 0.0, y: 0.0 },
+            Coord { x: 0.01, y: 0.01 },
+        ))
+        .unwrap();
+
+        let key = |c: &Coord| ((c.x * 1000.0).round() as i64, (c.y * 1000.0).round() as i64);
+        let mut nodes: HashMap<(i64, i64), NodeID> = HashMap::new();
+        let mut uses: HashMap<(i64, i64), usize> = HashMap::new();
+        for (pts, _) in &self.ways {
+            for (idx, pt) in pts.iter().enumerate() {
+                let next_id = NodeID(nodes.len() as i64 + 1);
+                nodes.entry(key(pt)).or_insert(next_id);
+                // Way endpoints always split
+                let count = if idx == 0 || idx == pts.len() - 1 {
+                    2
+                } else {
+                    1
+                };
+                *uses.entry(key(pt)).or_default() += count;
+            }
+        }
+
+        let mut intersections: BTreeMap<osm2graph::IntersectionID, osm2graph::Intersection> =
+            BTreeMap::new();
+        let mut intersection_at: HashMap<(i64, i64), osm2graph::IntersectionID> = HashMap::new();
+        let mut edges = BTreeMap::new();
+        for (way_idx, (pts, tags)) in self.ways.iter().enumerate() {
+            let mut current = vec![pts[0]];
+            for pt in &pts[1..] {
+                current.push(*pt);
+                if uses[&key(pt)] < 2 {
+                    continue;
+                }
+                let mut endpoint = |c: &Coord| {
+                    *intersection_at.entry(key(c)).or_insert_with(|| {
+                        let id = osm2graph::IntersectionID(intersections.len());
+                        intersections.insert(
+                            id,
+                            osm2graph::Intersection {
+                                id,
+                                edges: Vec::new(),
+                                osm_node: nodes[&key(c)],
+                                point: Point::from(*c),
+                            },
+                        );
+                        id
+                    })
+                };
+                let src = endpoint(&current[0]);
+                let dst = endpoint(pt);
+                let id = osm2graph::EdgeID(edges.len());
+                intersections.get_mut(&src).unwrap().edges.push(id);
+                intersections.get_mut(&dst).unwrap().edges.push(id);
+                edges.insert(
+                    id,
+                    osm2graph::Edge {
+                        id,
+                        src,
+                        dst,
+                        osm_way: WayID(way_idx as i64 + 1),
+                        osm_node1: nodes[&key(&current[0])],
+                        osm_node2: nodes[&key(pt)],
+                        osm_tags: tags.clone(),
+                        linestring: LineString::new(std::mem::take(&mut current)),
+                    },
+                );
+                current.push(*pt);
+            }
+        }
+
+        let boundary_polygon = Rect::new(
+            Coord {
+                x: -100.0,
+                y: -100.0,
+            },
+            Coord {
+                x: 1000.0,
+                y: 1000.0,
+            },
+        )
+        .to_polygon();
+
+        let mut areas = ReadOsmAreas::default();
+        for pt in &self.mini_roundabouts {
+            let mut tags = Tags::default();
+            tags.insert("highway", "mini_roundabout");
+            areas.node(nodes[&key(pt)], *pt, tags);
+        }
+
+        RoadBundler::from_graph(
+            osm2graph::Graph {
+                edges,
+                intersections,
+                mercator,
+                boundary_polygon,
+            },
+            areas,
+        )
+    }
+}
+
+/// Two blocks side by side. Road D, with `EdgeID(5)`, separates them, and road C, with
+/// `EdgeID(4)`, bounds the left one.
+pub fn two_blocks() -> RoadBundler {
+    TestMap::default()
+        .road(&[(0.0, 0.0), (100.0, 0.0), (200.0, 0.0)], "A")
+        .road(&[(0.0, 100.0), (100.0, 100.0), (200.0, 100.0)], "B")
+        .road(&[(0.0, 0.0), (0.0, 100.0)], "C")
+        .road(&[(100.0, 0.0), (100.0, 100.0)], "D")
+        .road(&[(200.0, 0.0), (200.0, 100.0)], "E")
+        .build()
+}
+
+/// Two oneway carriageways of Main Street, 20m apart and joined at both ends, with Side Street
+/// crossing between them. The northern carriageway runs along y = 0 and heads east.
+pub fn crossed_dc() -> TestMap {
+    TestMap::default()
+        .road(&[(-50.0, 10.0), (0.0, 10.0)], "Main Street")
+        .road(&[(200.0, 10.0), (250.0, 10.0)], "Main Street")
+        .oneway(
+            &[
+                (0.0, 10.0),
+                (20.0, 0.0),
+                (100.0, 0.0),
+                (180.0, 0.0),
+                (200.0, 10.0),
+            ],
+            "Main Street",
+        )
+        .oneway(
+            &[
+                (200.0, 10.0),
+                (180.0, 20.0),
+                (100.0, 20.0),
+                (20.0, 20.0),
+                (0.0, 10.0),
+            ],
+            "Main Street",
+        )
+        .road(
+            &[(100.0, -50.0), (100.0, 0.0), (100.0, 20.0), (100.0, 70.0)],
+            "Side Street",
+        )
+}
+
+pub fn edge_near(b: &RoadBundler, x: f64, y: f64) -> EdgeID {
+    let pt = Point::new(x, y);
+    b.graph
+        .edges
+        .values()
+        .min_by_key(|e| (Euclidean.distance(&e.linestring, &pt) * 100.0) as usize)
+        .unwrap()
+        .id
+}
+
+/// The smallest face containing the point, so never the face around the whole map
+pub fn face_containing(b: &RoadBundler, x: f64, y: f64) -> FaceID {
+    let pt = Point::new(x, y);
+    *b.faces
+        .iter()
+        .filter(|(_, face)| face.polygon.contains(&pt))
+        .min_by_key(|(_, face)| face.polygon.unsigned_area() as usize)
+        .unwrap()
+        .0
+}
@@ -1,8 +1,10 @@
 import { type Writable, writable } from "svelte/store";
 import * as backendPkg from "../../backend/pkg";
-import type { FeatureCollection } from "geojson";
+import type { FeatureCollection, MultiPolygon } from "geojson";
 
 export let backend: Writable<backendPkg.RoadBundler | null> = writable(null);
+// The OSM input `backend` was created from, needed again to export changes to it
+export let inputBytes: Writable<Uint8Array | null> = writable(null);
 
 export let controls: Writable<{
   showFaces: boolean;
@@ -31,17 +33,31 @@ export let tool: Writable<
   | "sidewalker"
 > = writable("explore");
 
+// How to build the center line of a collapsed dual carriageway
+export let centerLineMethod: Writable<"average" | "skeleton"> =
+  writable("average");
+
 export interface FaceProps {
-  face_id: number;
+  // A 64-bit integer, too big for a number
+  face_id: string;
   debug_hover: FeatureCollection;
   kind: "UrbanBlock" | "RoadArtifact" | "SidepathArtifact" | "OtherArea";
 
   dual_carriageway:
     | {
-        name: string;
+        name: string | null;
+        signal: "Name" | "Ref" | "Geometry";
+        carriageways: {
+          role: "Main" | "Service" | "Bus" | "Other";
+          edges: number[];
+        }[];
         debug_hover: FeatureCollection;
       }
     | string;
+  roundabout:
+    | { kind: "Ring"; circularity: number }
+    | { kind: "Mini"; node: number }
+    | string;
   sidepath: FeatureCollection | string;
   generated_sidewalks: FeatureCollection;
 }
@@ -72,6 +88,16 @@ export interface EdgeProps {
 
 export interface IntersectionProps {
   intersection_id: number;
+  osm_node?: number;
+  absorbed_ways: {
+    way: number;
+    node_ranges: [number, number][];
+    tags: Record<string, string>;
+  }[];
+  absorbed_nodes: number[];
+  roundabout?: "mini" | "collapsed";
+  // The area of faces collapsed into this intersection
+  footprint?: MultiPolygon;
 }
 
 // TODO Lost some of the specifity here, boo